@@ -4,9 +4,8 @@ use parser::{parser::Parser, scanner::Scanner};
 fn main() {
     let program = include_str!("../../../programs/mandelbrot.bf");
 
-    let mut scanner = Scanner::new(program.chars().collect());
-    let tokens = scanner.scan_tokens();
-    let parser = Parser::new(tokens);
+    let scanner = Scanner::new(program.as_bytes());
+    let parser = Parser::new(scanner);
     let ast_code = parser.parse_tokens().unwrap();
 
     let mut analyzer = Analyzer::new();
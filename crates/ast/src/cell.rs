@@ -0,0 +1,129 @@
+use std::str::FromStr;
+
+/// セルのビット幅
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CellWidth {
+    #[default]
+    U8,
+    U16,
+    U32,
+}
+
+impl CellWidth {
+    pub fn bits(self) -> u32 {
+        match self {
+            CellWidth::U8 => 8,
+            CellWidth::U16 => 16,
+            CellWidth::U32 => 32,
+        }
+    }
+
+    /// セルが持てる最大の値．-1と同じビット列
+    pub fn max(self) -> u32 {
+        u32::MAX >> (32 - self.bits())
+    }
+}
+
+impl FromStr for CellWidth {
+    type Err = String;
+
+    /// `8`，`16`，`32`，または`u8`，`u16`，`u32`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix('u').unwrap_or(s) {
+            "8" => Ok(CellWidth::U8),
+            "16" => Ok(CellWidth::U16),
+            "32" => Ok(CellWidth::U32),
+            _ => Err(format!("invalid cell width: {}", s)),
+        }
+    }
+}
+
+/// セルの値が範囲外になった時の扱い
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// 反対側の端に折り返す
+    #[default]
+    Wrapping,
+    /// 実行時エラーにする．コンパイルしたプログラムは終了コード1で終わる
+    Checked,
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wrapping" => Ok(Overflow::Wrapping),
+            "checked" => Ok(Overflow::Checked),
+            _ => Err(format!("unknown overflow mode: {}", s)),
+        }
+    }
+}
+
+/// セルの幅と，範囲外になった時の扱い．全てのバックエンドと最適化で同じように振る舞う
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CellConfig {
+    pub width: CellWidth,
+    pub overflow: Overflow,
+}
+
+impl CellConfig {
+    pub fn new(width: CellWidth, overflow: Overflow) -> Self {
+        Self { width, overflow }
+    }
+
+    pub fn is_wrapping(&self) -> bool {
+        self.overflow == Overflow::Wrapping
+    }
+
+    /// 値を`diff`だけ増減する．`Checked`で範囲外になる場合は`None`
+    pub fn add(&self, value: u32, diff: i128) -> Option<u32> {
+        let sum = value as i128 + diff;
+        match self.overflow {
+            Overflow::Wrapping => Some(self.wrap(sum)),
+            Overflow::Checked => (0..=self.width.max() as i128)
+                .contains(&sum)
+                .then_some(sum as u32),
+        }
+    }
+
+    /// `target`に`value * factor`を加える．`[->++<]`などを1度に実行した結果
+    pub fn multiply_add(&self, target: u32, value: u32, factor: isize) -> Option<u32> {
+        self.add(target, value as i128 * factor as i128)
+    }
+
+    /// 幅に合わせて折り返す
+    pub fn wrap(&self, n: i128) -> u32 {
+        // 2の補数で下位32ビットを取り出してから幅に合わせる
+        n as u32 & self.width.max()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_or_checks() {
+        let wrapping = CellConfig::new(CellWidth::U16, Overflow::Wrapping);
+        assert_eq!(wrapping.add(65535, 1), Some(0));
+        assert_eq!(wrapping.add(0, -1), Some(65535));
+        assert_eq!(wrapping.multiply_add(1, 300, 300), Some(24465));
+
+        let checked = CellConfig::new(CellWidth::U8, Overflow::Checked);
+        assert_eq!(checked.add(255, 1), None);
+        assert_eq!(checked.add(0, -1), None);
+        assert_eq!(checked.add(250, 5), Some(255));
+
+        let wide = CellConfig::new(CellWidth::U32, Overflow::Wrapping);
+        assert_eq!(wide.add(u32::MAX, 1), Some(0));
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("u16".parse(), Ok(CellWidth::U16));
+        assert_eq!("32".parse(), Ok(CellWidth::U32));
+        assert!("u64".parse::<CellWidth>().is_err());
+        assert_eq!("checked".parse(), Ok(Overflow::Checked));
+    }
+}
@@ -0,0 +1,201 @@
+use crate::cell::CellConfig;
+use crate::inst::{Ast, AstCode};
+
+/// AstCodeを実行可能なBrainfuckのソースコードに戻す．セルは8ビットで折り返すものとする
+///
+/// 連続する`+`と`-`，`<`と`>`は打ち消し合う分を取り除いて書く．
+/// 最適化で作られた命令は，それと同じ動作をするループとして書く
+pub fn emit(code: &AstCode) -> String {
+    emit_with_cells(code, CellConfig::default())
+}
+
+/// `cells`のセルで同じ動作をするBrainfuckのソースコードに戻す
+///
+/// 折り返すセルでは，`Load`を`+`と`-`の短い方で書く
+pub fn emit_with_cells(code: &AstCode, cells: CellConfig) -> String {
+    let mut emitter = Emitter::default();
+
+    // 深くネストしたプログラムでもスタックを消費しないように，再帰は使わない
+    let mut stack: Vec<std::slice::Iter<Ast>> = vec![code.vec().iter()];
+    while let Some(instructions) = stack.last_mut() {
+        let Some(instruction) = instructions.next() else {
+            stack.pop();
+            if !stack.is_empty() {
+                emitter.push_str("]");
+            }
+            continue;
+        };
+
+        match instruction {
+            Ast::InclementPointer(count) => emitter.move_pointer(*count as isize),
+            Ast::DecrementPointer(count) => emitter.move_pointer(-(*count as isize)),
+            Ast::InclementValue(count) => emitter.add_value(*count as isize),
+            Ast::DecrementValue(count) => emitter.add_value(-(*count as isize)),
+            Ast::Output => emitter.push_str("."),
+            Ast::Input => emitter.push_str(","),
+            Ast::Loop(code) => {
+                emitter.push_str("[");
+                stack.push(code.vec().iter());
+            }
+            Ast::Load(n) => {
+                emitter.push_str("[-]");
+                let up = *n as u64;
+                let down = cells.width.max() as u64 + 1 - up;
+                if cells.is_wrapping() && down < up {
+                    emitter.push_repeat('-', down as usize);
+                } else {
+                    emitter.push_repeat('+', up as usize);
+                }
+            }
+            Ast::SumRight(count) => {
+                emitter.push_str("[-");
+                emitter.push_repeat('>', *count);
+                emitter.push_str("+");
+                emitter.push_repeat('<', *count);
+                emitter.push_str("]");
+            }
+            Ast::SumLeft(count) => {
+                emitter.push_str("[-");
+                emitter.push_repeat('<', *count);
+                emitter.push_str("+");
+                emitter.push_repeat('>', *count);
+                emitter.push_str("]");
+            }
+            Ast::MultiplyAdd(targets) => {
+                emitter.push_str("[-");
+                let mut position = 0;
+                for &(offset, factor) in targets {
+                    emitter.move_pointer(offset - position);
+                    emitter.add_value(factor);
+                    position = offset;
+                }
+                emitter.move_pointer(-position);
+                emitter.push_str("]");
+            }
+            Ast::JumpZeroRight { per } => {
+                emitter.push_str("[");
+                emitter.push_repeat('>', *per);
+                emitter.push_str("]");
+            }
+            Ast::JumpZeroLeft { per } => {
+                emitter.push_str("[");
+                emitter.push_repeat('<', *per);
+                emitter.push_str("]");
+            }
+        }
+    }
+
+    emitter.flush();
+    emitter.result
+}
+
+#[derive(Default)]
+struct Emitter {
+    result: String,
+    /// まだ書き出していないポインタの移動量
+    pointer: isize,
+    /// まだ書き出していない値の増減
+    value: isize,
+}
+
+impl Emitter {
+    fn move_pointer(&mut self, diff: isize) {
+        if self.value != 0 {
+            self.flush();
+        }
+        self.pointer += diff;
+    }
+
+    fn add_value(&mut self, diff: isize) {
+        if self.pointer != 0 {
+            self.flush();
+        }
+        self.value += diff;
+    }
+
+    fn push_str(&mut self, s: &str) {
+        self.flush();
+        self.result.push_str(s);
+    }
+
+    fn push_repeat(&mut self, c: char, count: usize) {
+        self.flush();
+        self.result.extend(std::iter::repeat_n(c, count));
+    }
+
+    /// 溜めておいた移動と増減を書き出す
+    fn flush(&mut self) {
+        let (value, pointer) = (self.value, self.pointer);
+        self.value = 0;
+        self.pointer = 0;
+
+        let sign = if value >= 0 { '+' } else { '-' };
+        self.result
+            .extend(std::iter::repeat_n(sign, value.unsigned_abs()));
+        let direction = if pointer >= 0 { '>' } else { '<' };
+        self.result
+            .extend(std::iter::repeat_n(direction, pointer.unsigned_abs()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancels_redundant_pairs() {
+        let code = AstCode::new(vec![
+            Ast::InclementValue(3),
+            Ast::DecrementValue(5),
+            Ast::InclementPointer(2),
+            Ast::DecrementPointer(1),
+            Ast::InclementPointer(1),
+            Ast::DecrementPointer(2),
+            Ast::Output,
+            Ast::InclementValue(1),
+            Ast::DecrementValue(1),
+            Ast::InclementPointer(1),
+            Ast::InclementValue(2),
+            Ast::DecrementPointer(1),
+            Ast::Loop(AstCode::new(vec![
+                Ast::InclementPointer(1),
+                Ast::DecrementPointer(1),
+            ])),
+        ]);
+
+        assert_eq!(emit(&code), "--.>++<[]");
+    }
+
+    #[test]
+    fn emits_optimized_instructions() {
+        let code = AstCode::new(vec![
+            Ast::Load(2),
+            Ast::SumRight(2),
+            Ast::SumLeft(1),
+            Ast::JumpZeroRight { per: 3 },
+            Ast::JumpZeroLeft { per: 1 },
+            Ast::MultiplyAdd(vec![(-1, 2), (1, 3), (2, -2)]),
+            Ast::Input,
+        ]);
+
+        assert_eq!(emit(&code), "[-]++[->>+<<][-<+>][>>>][<][-<++>>+++>--<<],");
+    }
+
+    #[test]
+    fn loads_with_shorter_direction() {
+        use crate::cell::{CellWidth, Overflow};
+
+        let code = AstCode::new(vec![Ast::Load(255), Ast::Load(2)]);
+        assert_eq!(emit(&code), "[-]-[-]++");
+
+        let checked = CellConfig::new(CellWidth::U8, Overflow::Checked);
+        assert_eq!(
+            emit_with_cells(&code, checked),
+            format!("[-]{}[-]++", "+".repeat(255))
+        );
+
+        let wide = CellConfig::new(CellWidth::U16, Overflow::Wrapping);
+        let code = AstCode::new(vec![Ast::Load(65535)]);
+        assert_eq!(emit_with_cells(&code, wide), "[-]-");
+    }
+}
@@ -0,0 +1,29 @@
+use std::str::FromStr;
+
+/// 入力が終わった後に`,`を実行した時の扱い．全てのバックエンドで同じように振る舞う
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EofPolicy {
+    /// 実行時エラーにする．コンパイルしたプログラムは終了コード1で終わる
+    #[default]
+    Error,
+    /// セルを変えない
+    Unchanged,
+    /// セルを0にする
+    Zero,
+    /// セルを-1（全てのビットが1）にする
+    MinusOne,
+}
+
+impl FromStr for EofPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(EofPolicy::Error),
+            "unchanged" => Ok(EofPolicy::Unchanged),
+            "zero" | "0" => Ok(EofPolicy::Zero),
+            "minus-one" | "-1" => Ok(EofPolicy::MinusOne),
+            _ => Err(format!("unknown EOF policy: {}", s)),
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use std::fmt::{Debug, Display};
 
+use crate::source_map::{SourceMap, SourceNode, SourceRange};
+
 #[derive(Debug, Clone, PartialEq, Eq, Default, Hash)]
 pub struct AstCode(Vec<Ast>);
 
@@ -15,6 +17,34 @@ impl AstCode {
     pub fn vec_mut(&mut self) -> &mut Vec<Ast> {
         &mut self.0
     }
+
+    /// ループの中も含めた全てのノードの数
+    pub fn node_count(&self) -> usize {
+        let mut count = 0;
+        let mut stack: Vec<&AstCode> = vec![self];
+        while let Some(code) = stack.pop() {
+            count += code.0.len();
+            for ast in code.0.iter() {
+                if let Ast::Loop(body) = ast {
+                    stack.push(body);
+                }
+            }
+        }
+
+        count
+    }
+}
+
+impl Drop for AstCode {
+    /// 深くネストしたループを再帰せずに解放する
+    fn drop(&mut self) {
+        let mut stack = std::mem::take(&mut self.0);
+        while let Some(ast) = stack.pop() {
+            if let Ast::Loop(mut code) = ast {
+                stack.append(&mut code.0);
+            }
+        }
+    }
 }
 
 impl Display for AstCode {
@@ -38,11 +68,14 @@ pub enum Ast {
     Loop(AstCode),
 
     /// 数を書き込む
-    Load(u8),
+    Load(u32),
     /// 現在の値をcount個右のセルに加える．
     SumRight(usize),
     /// 現在の値をcount個左のセルに加える．
     SumLeft(usize),
+    /// 現在の値に係数を掛けて，相対位置のセルにそれぞれ加え，現在のセルを0にする．
+    /// 要素は(相対位置, 係数)で，相対位置の昇順に並ぶ
+    MultiplyAdd(Vec<(isize, isize)>),
     /// per毎にメモリを右方向に見ていって，0なら終わる
     JumpZeroRight {
         per: usize,
@@ -66,6 +99,7 @@ impl Display for Ast {
             Ast::Load(n) => write!(f, "Load({})", n),
             Ast::SumRight(count) => write!(f, "SumRight({})", count),
             Ast::SumLeft(count) => write!(f, "SumLeft({})", count),
+            Ast::MultiplyAdd(targets) => write!(f, "MultiplyAdd({:?})", targets),
             Ast::JumpZeroRight { per } => write!(f, "JumpZeroRight(per:{})", per),
             Ast::JumpZeroLeft { per } => write!(f, "JumpZeroLeft(per:{})", per),
         }
@@ -100,11 +134,13 @@ pub enum Op {
         if_non_zero_sub: usize,
     },
     /// 数を書き込む
-    Load(u8),
+    Load(u32),
     /// 現在の値をcount個右のセルに加える
     SumRight(usize),
     /// 現在の値をcount個左のセルに加える
     SumLeft(usize),
+    /// 現在の値に係数を掛けて，相対位置のセルにそれぞれ加え，現在のセルを0にする
+    MultiplyAdd(Vec<(isize, isize)>),
     /// per毎にメモリを右方向に見ていって，0なら終わる
     JumpZeroRight {
         per: usize,
@@ -113,42 +149,188 @@ pub enum Op {
     JumpZeroLeft {
         per: usize,
     },
+
+    /// 相対位置offsetのセルにcountを加える
+    InclementValueAt {
+        offset: isize,
+        count: usize,
+    },
+    /// 相対位置offsetのセルからcountを引く
+    DecrementValueAt {
+        offset: isize,
+        count: usize,
+    },
+    /// 相対位置offsetのセルを出力する
+    OutputAt {
+        offset: isize,
+    },
+    /// 相対位置offsetのセルに入力する
+    InputAt {
+        offset: isize,
+    },
+    /// 相対位置offsetのセルに数を書き込む
+    LoadAt {
+        offset: isize,
+        n: u32,
+    },
 }
 
-impl From<AstCode> for OpCode {
-    fn from(value: AstCode) -> Self {
-        let mut result: Vec<Op> = Vec::with_capacity(value.0.len());
-        for instruction in value.0 {
-            match instruction {
-                Ast::InclementPointer(count) => result.push(Op::InclementPointer(count)),
-                Ast::DecrementPointer(count) => result.push(Op::DecrementPointer(count)),
-                Ast::InclementValue(count) => result.push(Op::InclementValue(count)),
-                Ast::DecrementValue(count) => result.push(Op::DecrementValue(count)),
-                Ast::Output => result.push(Op::Output),
-                Ast::Input => result.push(Op::Input),
-                Ast::Loop(code) => {
-                    result.push(Op::LoopStart {
-                        if_zero_add: 0,
-                    });
-                    let loop_start_index = result.len() - 1;
-                    let loop_code: OpCode = code.into();
-                    result.extend(loop_code.0);
-                    result.push(Op::LoopEnd {
-                        if_non_zero_sub: result.len() - loop_start_index,
-                    });
-                    result[loop_start_index] = Op::LoopStart {
-                        if_zero_add: result.len() - loop_start_index - 1,
+/// ポインタの移動を遅らせて，値の操作と入出力を相対位置で表す
+///
+/// 溜まった移動は，現在のセルを使う命令の前とループの境界で書き出す
+#[derive(Default)]
+struct PointerOffset {
+    offset: isize,
+    /// 溜まった移動の元になった範囲
+    range: SourceRange,
+}
+
+impl PointerOffset {
+    fn add(&mut self, diff: isize, range: SourceRange) {
+        self.offset += diff;
+        self.range = self.range.merge(range);
+    }
+
+    fn flush(&mut self, result: &mut Lowered) {
+        let offset = std::mem::take(&mut self.offset);
+        let range = std::mem::take(&mut self.range);
+        match offset {
+            0 => {}
+            1.. => result.push(Op::InclementPointer(offset as usize), range),
+            _ => result.push(Op::DecrementPointer(offset.unsigned_abs()), range),
+        }
+    }
+
+    /// 現在のセルを使う命令を，溜まった移動を書き出してから追加する
+    fn push(&mut self, result: &mut Lowered, op: Op, range: SourceRange) {
+        self.flush(result);
+        result.push(op, range);
+    }
+}
+
+/// 変換した命令と，命令ごとのソースコード上の範囲
+#[derive(Default)]
+struct Lowered {
+    ops: Vec<Op>,
+    ranges: Vec<SourceRange>,
+}
+
+impl Lowered {
+    fn push(&mut self, op: Op, range: SourceRange) {
+        self.ops.push(op);
+        self.ranges.push(range);
+    }
+}
+
+/// 処理中のブロックの残りとその範囲，ループであればそのLoopStartの位置とループの範囲
+type LowerFrame = (
+    std::vec::IntoIter<Ast>,
+    std::vec::IntoIter<SourceNode>,
+    Option<(usize, SourceRange)>,
+);
+
+impl OpCode {
+    /// `OpCode`に変換し，命令と同じ添字に，元になったソースコード上の範囲を返す
+    ///
+    /// ループの`LoopStart`は`[`，`LoopEnd`は`]`の範囲を持つ
+    pub fn from_mapped(mut code: AstCode, mut map: SourceMap) -> (Self, Vec<SourceRange>) {
+        let mut result = Lowered::default();
+        let mut stack: Vec<LowerFrame> = vec![(
+            std::mem::take(&mut code.0).into_iter(),
+            std::mem::take(map.vec_mut()).into_iter(),
+            None,
+        )];
+
+        let mut offset = PointerOffset::default();
+
+        while let Some((instructions, nodes, loop_start)) = stack.last_mut() {
+            let Some(instruction) = instructions.next() else {
+                offset.flush(&mut result);
+                if let Some((loop_start_index, range)) = *loop_start {
+                    result.push(
+                        Op::LoopEnd {
+                            if_non_zero_sub: result.ops.len() - loop_start_index,
+                        },
+                        range.last(),
+                    );
+                    result.ops[loop_start_index] = Op::LoopStart {
+                        if_zero_add: result.ops.len() - loop_start_index - 1,
                     };
                 }
-                Ast::Load(n) => result.push(Op::Load(n)),
-                Ast::SumRight(count) => result.push(Op::SumRight(count)),
-                Ast::SumLeft(count) => result.push(Op::SumLeft(count)),
-                Ast::JumpZeroRight { per } => result.push(Op::JumpZeroRight { per }),
-                Ast::JumpZeroLeft { per } => result.push(Op::JumpZeroLeft { per }),
+                stack.pop();
+                continue;
+            };
+            // 表の形が合わなければ，足りない分は範囲が分からないものとする
+            let SourceNode { range, body } = nodes.next().unwrap_or_default();
+
+            match instruction {
+                Ast::InclementPointer(count) => offset.add(count as isize, range),
+                Ast::DecrementPointer(count) => offset.add(-(count as isize), range),
+                Ast::InclementValue(count) => result.push(
+                    Op::InclementValueAt {
+                        offset: offset.offset,
+                        count,
+                    },
+                    range,
+                ),
+                Ast::DecrementValue(count) => result.push(
+                    Op::DecrementValueAt {
+                        offset: offset.offset,
+                        count,
+                    },
+                    range,
+                ),
+                Ast::Output => result.push(
+                    Op::OutputAt {
+                        offset: offset.offset,
+                    },
+                    range,
+                ),
+                Ast::Input => result.push(
+                    Op::InputAt {
+                        offset: offset.offset,
+                    },
+                    range,
+                ),
+                Ast::Load(n) => result.push(
+                    Op::LoadAt {
+                        offset: offset.offset,
+                        n,
+                    },
+                    range,
+                ),
+                Ast::Loop(mut code) => {
+                    offset.push(&mut result, Op::LoopStart { if_zero_add: 0 }, range.first());
+                    let loop_start_index = result.ops.len() - 1;
+                    let mut body = body;
+                    stack.push((
+                        std::mem::take(&mut code.0).into_iter(),
+                        std::mem::take(body.vec_mut()).into_iter(),
+                        Some((loop_start_index, range)),
+                    ));
+                }
+                Ast::SumRight(count) => offset.push(&mut result, Op::SumRight(count), range),
+                Ast::SumLeft(count) => offset.push(&mut result, Op::SumLeft(count), range),
+                Ast::MultiplyAdd(targets) => {
+                    offset.push(&mut result, Op::MultiplyAdd(targets), range)
+                }
+                Ast::JumpZeroRight { per } => {
+                    offset.push(&mut result, Op::JumpZeroRight { per }, range)
+                }
+                Ast::JumpZeroLeft { per } => {
+                    offset.push(&mut result, Op::JumpZeroLeft { per }, range)
+                }
             }
         }
 
-        Self(result)
+        (Self(result.ops), result.ranges)
+    }
+}
+
+impl From<AstCode> for OpCode {
+    fn from(value: AstCode) -> Self {
+        let map = SourceMap::unknown(&value);
+        Self::from_mapped(value, map).0
     }
 }
 
@@ -189,8 +371,14 @@ impl Debug for Op {
             Op::Load(n) => write!(f, "Load({})", n),
             Op::SumRight(count) => write!(f, "SumRight({})", count),
             Op::SumLeft(count) => write!(f, "SumLeft({})", count),
+            Op::MultiplyAdd(targets) => write!(f, "MultiplyAdd({:?})", targets),
             Op::JumpZeroRight { per } => write!(f, "JumpZeroRight(per:{})", per),
             Op::JumpZeroLeft { per } => write!(f, "JumpZeroLeft(per:{})", per),
+            Op::InclementValueAt { offset, count } => write!(f, "+ ({}) @{}", count, offset),
+            Op::DecrementValueAt { offset, count } => write!(f, "- ({}) @{}", count, offset),
+            Op::OutputAt { offset } => write!(f, "Output @{}", offset),
+            Op::InputAt { offset } => write!(f, "Input @{}", offset),
+            Op::LoadAt { offset, n } => write!(f, "Load({}) @{}", n, offset),
         }
     }
 }
@@ -211,12 +399,89 @@ mod tests {
         assert_eq!(
             op.0,
             vec![
+                Op::InclementValueAt {
+                    offset: 2,
+                    count: 1
+                },
                 Op::InclementPointer(2),
-                Op::InclementValue(1),
-                Op::LoopStart { if_zero_add: 3 },
-                Op::InclementValue(1),
+                Op::LoopStart { if_zero_add: 2 },
+                Op::InclementValueAt {
+                    offset: 0,
+                    count: 1
+                },
                 Op::LoopEnd { if_non_zero_sub: 2 },
             ]
         );
     }
+
+    #[test]
+    fn merge_pointer_moves() {
+        // >+>-<<.[>,<<]>
+        let code = AstCode::new(vec![
+            Ast::InclementPointer(1),
+            Ast::InclementValue(1),
+            Ast::InclementPointer(1),
+            Ast::DecrementValue(1),
+            Ast::DecrementPointer(2),
+            Ast::Output,
+            Ast::Loop(AstCode::new(vec![
+                Ast::InclementPointer(1),
+                Ast::Input,
+                Ast::DecrementPointer(2),
+            ])),
+            Ast::InclementPointer(1),
+        ]);
+
+        assert_eq!(
+            OpCode::from(code).0,
+            vec![
+                Op::InclementValueAt {
+                    offset: 1,
+                    count: 1
+                },
+                Op::DecrementValueAt {
+                    offset: 2,
+                    count: 1
+                },
+                Op::OutputAt { offset: 0 },
+                Op::LoopStart { if_zero_add: 3 },
+                Op::InputAt { offset: 1 },
+                Op::DecrementPointer(1),
+                Op::LoopEnd { if_non_zero_sub: 3 },
+                Op::InclementPointer(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn deeply_nested_ast_to_op() {
+        const DEPTH: usize = 1_000_000;
+        let mut code = AstCode::new(vec![Ast::InclementValue(1)]);
+        for _ in 0..DEPTH {
+            code = AstCode::new(vec![Ast::Loop(code)]);
+        }
+
+        let op = OpCode::from(code);
+
+        assert_eq!(op.0.len(), DEPTH * 2 + 1);
+        assert_eq!(
+            op.0[0],
+            Op::LoopStart {
+                if_zero_add: DEPTH * 2
+            }
+        );
+        assert_eq!(
+            op.0[DEPTH],
+            Op::InclementValueAt {
+                offset: 0,
+                count: 1
+            }
+        );
+        assert_eq!(
+            op.0[DEPTH * 2],
+            Op::LoopEnd {
+                if_non_zero_sub: DEPTH * 2
+            }
+        );
+    }
 }
@@ -1,2 +1,7 @@
+pub mod cell;
+pub mod emit;
+pub mod eof;
 pub mod inst;
 pub mod opt;
+pub mod source_map;
+pub mod tape;
@@ -1,109 +1,491 @@
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display};
+use std::str::FromStr;
+
+use crate::cell::CellConfig;
 use crate::inst::{Ast, AstCode};
+use crate::source_map::{SourceMap, SourceNode, SourceRange};
+use crate::tape::{TapeConfig, DEFAULT_MAX_LEN};
+
+mod dead_loops;
+mod partial_eval;
+
+pub use dead_loops::DeadLoops;
+pub use partial_eval::PartialEval;
+
+/// 最適化したプログラムを実行する環境．パスはこれを前提にしてよい
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Target {
+    pub cells: CellConfig,
+    /// 開始時に全てのセルが0か．REPLのように前のテープを引き継ぐ時は`false`
+    pub zeroed_entry: bool,
+    /// テープの形．`Circular`では離れた相対位置が同じセルになることがある
+    pub tape: TapeConfig,
+    /// 伸びるテープの長さの上限
+    pub max_tape_len: usize,
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Self {
+            cells: CellConfig::default(),
+            zeroed_entry: true,
+            tape: TapeConfig::default(),
+            max_tape_len: DEFAULT_MAX_LEN,
+        }
+    }
+}
+
+/// 最適化パス
+pub trait Pass: Debug {
+    /// `--disable-pass`などで指定する名前
+    fn name(&self) -> &'static str;
+    /// `target`で実行した時の動作を変えないように書き換える
+    fn run(&self, code: AstCode, target: &Target) -> AstCode;
+
+    /// `run`と同じく書き換え，`map`も書き換えた後のノードに合わせる
+    ///
+    /// 既定では，書き換えた後の全てのノードをプログラム全体の範囲に対応させる
+    fn run_mapped(&self, code: AstCode, map: SourceMap, target: &Target) -> (AstCode, SourceMap) {
+        let range = map.range();
+        let code = self.run(code, target);
+        let map = SourceMap::filled(&code, range);
+        (code, map)
+    }
+}
+
+/// 連続する`+ - < >`をまとめる
+#[derive(Debug, Clone, Copy)]
+pub struct RunLength;
+
+impl Pass for RunLength {
+    fn name(&self) -> &'static str {
+        "run-length"
+    }
 
-#[derive(Debug, Default)]
-pub struct Optimizer {}
+    fn run(&self, code: AstCode, _target: &Target) -> AstCode {
+        run_length_optimize(code)
+    }
+
+    fn run_mapped(&self, code: AstCode, map: SourceMap, _target: &Target) -> (AstCode, SourceMap) {
+        run_length_mapped(code, map)
+    }
+}
+
+/// ループをパターンに合わせて専用の命令に置き換える
+#[derive(Debug, Clone, Copy)]
+pub struct ReplaceLoops;
+
+impl Pass for ReplaceLoops {
+    fn name(&self) -> &'static str {
+        "replace-loops"
+    }
+
+    fn run(&self, code: AstCode, target: &Target) -> AstCode {
+        replace_patterns(code, &target.cells)
+    }
+
+    fn run_mapped(&self, code: AstCode, map: SourceMap, target: &Target) -> (AstCode, SourceMap) {
+        replace_patterns_mapped(code, map, &target.cells)
+    }
+}
+
+/// 名前からパスを得る
+pub fn named_pass(name: &str) -> Option<Box<dyn Pass>> {
+    match name {
+        "run-length" => Some(Box::new(RunLength)),
+        "replace-loops" => Some(Box::new(ReplaceLoops)),
+        "dead-loops" => Some(Box::new(DeadLoops)),
+        "partial-eval" => Some(Box::new(PartialEval::default())),
+        _ => None,
+    }
+}
+
+/// 最適化レベル
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum OptLevel {
+    /// 最適化しない
+    O0,
+    /// 連続する命令をまとめる
+    O1,
+    /// ループのパターンの置き換えと実行されないループの削除もし，ノード数が減らなくなるまで繰り返す
+    #[default]
+    O2,
+    /// 入力を読む前の部分もコンパイル時に実行する
+    O3,
+}
+
+impl FromStr for OptLevel {
+    type Err = String;
+
+    /// `0`から`3`，または`O0`から`O3`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix('O').unwrap_or(s) {
+            "0" => Ok(OptLevel::O0),
+            "1" => Ok(OptLevel::O1),
+            "2" => Ok(OptLevel::O2),
+            "3" => Ok(OptLevel::O3),
+            _ => Err(format!("invalid optimization level: {}", s)),
+        }
+    }
+}
+
+/// 1回のパスの実行の前後のノード数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassStats {
+    pub name: &'static str,
+    /// 何周目のパスか．1から数える
+    pub iteration: usize,
+    pub nodes_before: usize,
+    pub nodes_after: usize,
+}
+
+impl Display for PassStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} #{}: {} -> {} nodes",
+            self.name, self.iteration, self.nodes_before, self.nodes_after
+        )
+    }
+}
+
+/// パスを順に実行する
+#[derive(Debug)]
+pub struct Optimizer {
+    passes: Vec<Box<dyn Pass>>,
+    /// ノード数が減らなくなるまでパスの列を繰り返すか
+    fixpoint: bool,
+    target: Target,
+}
+
+impl Default for Optimizer {
+    fn default() -> Self {
+        Self::with_level(OptLevel::default())
+    }
+}
 
 impl Optimizer {
     pub fn new() -> Self {
-        Self {}
+        Self::default()
+    }
+
+    pub fn with_level(level: OptLevel) -> Self {
+        let mut passes: Vec<Box<dyn Pass>> = Vec::new();
+        if level >= OptLevel::O1 {
+            passes.push(Box::new(RunLength));
+        }
+        if level >= OptLevel::O2 {
+            passes.push(Box::new(ReplaceLoops));
+            passes.push(Box::new(DeadLoops));
+        }
+        if level >= OptLevel::O3 {
+            passes.push(Box::new(PartialEval::default()));
+        }
+
+        Self {
+            passes,
+            fixpoint: level >= OptLevel::O2,
+            target: Target::default(),
+        }
+    }
+
+    /// パスを最後に加える
+    pub fn with_pass(mut self, pass: Box<dyn Pass>) -> Self {
+        self.passes.push(pass);
+        self
+    }
+
+    /// 名前が`name`のパスを取り除く
+    pub fn without_pass(mut self, name: &str) -> Self {
+        self.passes.retain(|pass| pass.name() != name);
+        self
+    }
+
+    pub fn with_fixpoint(mut self, fixpoint: bool) -> Self {
+        self.fixpoint = fixpoint;
+        self
+    }
+
+    /// 最適化したプログラムを実行するセルの幅と折り返し
+    pub fn with_cells(mut self, cells: CellConfig) -> Self {
+        self.target.cells = cells;
+        self
+    }
+
+    /// 開始時に全てのセルが0と仮定してよいか．`false`では前のテープの値が残っていても動作を変えない
+    pub fn with_zeroed_entry(mut self, zeroed: bool) -> Self {
+        self.target.zeroed_entry = zeroed;
+        self
+    }
+
+    /// 最適化したプログラムを実行するテープ．端が繋がったテープでは，セルの値を追跡するパスは何もしなくなる
+    pub fn with_tape(mut self, tape: TapeConfig) -> Self {
+        self.target.tape = tape;
+        self
+    }
+
+    /// 伸びるテープの長さの上限
+    pub fn with_max_tape_len(mut self, max_len: usize) -> Self {
+        self.target.max_tape_len = max_len;
+        self
+    }
+
+    /// 実行するパスの名前
+    pub fn pass_names(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
     }
 
-    #[allow(clippy::let_and_return)]
     pub fn optimize(&self, code: AstCode) -> AstCode {
-        let code = run_length_optimize(code);
-        let code = replace_patterns(code);
-        code
+        self.optimize_with_stats(code).0
+    }
+
+    /// 最適化し，実行したパスごとの統計を返す
+    pub fn optimize_with_stats(&self, code: AstCode) -> (AstCode, Vec<PassStats>) {
+        let map = SourceMap::unknown(&code);
+        let (code, _, stats) = self.optimize_mapped(code, map);
+        (code, stats)
+    }
+
+    /// 最適化し，`map`を最適化した後のノードに合わせる．実行したパスごとの統計も返す
+    pub fn optimize_mapped(
+        &self,
+        code: AstCode,
+        map: SourceMap,
+    ) -> (AstCode, SourceMap, Vec<PassStats>) {
+        let (mut code, mut map) = (code, map);
+        let mut stats = Vec::new();
+        let mut node_count = code.node_count();
+
+        for iteration in 1.. {
+            let start_node_count = node_count;
+            for pass in self.passes.iter() {
+                (code, map) = pass.run_mapped(code, map, &self.target);
+
+                let new_node_count = code.node_count();
+                stats.push(PassStats {
+                    name: pass.name(),
+                    iteration,
+                    nodes_before: node_count,
+                    nodes_after: new_node_count,
+                });
+                node_count = new_node_count;
+            }
+
+            if !self.fixpoint || self.passes.is_empty() || node_count >= start_node_count {
+                break;
+            }
+        }
+
+        (code, map, stats)
     }
 }
 
 fn run_length_optimize(code: AstCode) -> AstCode {
+    let map = SourceMap::unknown(&code);
+    run_length_mapped(code, map).0
+}
+
+fn run_length_mapped(code: AstCode, map: SourceMap) -> (AstCode, SourceMap) {
     macro_rules! impl_run_length_optimize {
-        ($variant:path, $result:expr, $count:expr) => {
-            if let Some($variant(last)) = $result.last_mut() {
+        ($variant:path, $result:expr, $count:expr, $node:expr) => {
+            if let Some(($variant(last), last_node)) = $result.last_mut() {
                 *last += $count;
+                last_node.merge(&$node);
             } else {
-                $result.push($variant(*$count));
+                $result.push(($variant($count), $node));
             }
         };
     }
-    let vec = code.vec();
-    let mut result: Vec<Ast> = Vec::new();
 
-    for ast in vec {
-        match ast {
-            Ast::InclementPointer(count) => {
-                impl_run_length_optimize!(Ast::InclementPointer, result, count)
-            }
-            Ast::DecrementPointer(count) => {
-                impl_run_length_optimize!(Ast::DecrementPointer, result, count)
-            }
-            Ast::InclementValue(count) => {
-                impl_run_length_optimize!(Ast::InclementValue, result, count)
-            }
-            Ast::DecrementValue(count) => {
-                impl_run_length_optimize!(Ast::DecrementValue, result, count)
-            }
-            Ast::Loop(l) => {
-                let l = run_length_optimize(l.clone());
-                result.push(Ast::Loop(l));
+    map_blocks(code, map, |block| {
+        let mut result: Block = Vec::with_capacity(block.len());
+
+        for (ast, node) in block {
+            match ast {
+                Ast::InclementPointer(count) => {
+                    impl_run_length_optimize!(Ast::InclementPointer, result, count, node)
+                }
+                Ast::DecrementPointer(count) => {
+                    impl_run_length_optimize!(Ast::DecrementPointer, result, count, node)
+                }
+                Ast::InclementValue(count) => {
+                    impl_run_length_optimize!(Ast::InclementValue, result, count, node)
+                }
+                Ast::DecrementValue(count) => {
+                    impl_run_length_optimize!(Ast::DecrementValue, result, count, node)
+                }
+                Ast::Loop(_)
+                | Ast::Output
+                | Ast::Input
+                | Ast::Load(_)
+                | Ast::SumRight(_)
+                | Ast::SumLeft(_)
+                | Ast::MultiplyAdd(_)
+                | Ast::JumpZeroRight { .. }
+                | Ast::JumpZeroLeft { .. } => result.push((ast, node)),
             }
-            Ast::Output
-            | Ast::Input
-            | Ast::Load(_)
-            | Ast::SumRight(_)
-            | Ast::SumLeft(_)
-            | Ast::JumpZeroRight { .. }
-            | Ast::JumpZeroLeft { .. } => result.push(ast.clone()),
         }
-    }
 
-    AstCode::new(result)
+        result
+    })
 }
 
-fn replace_patterns(mut code: AstCode) -> AstCode {
-    for ast in code.vec_mut() {
-        if let Ast::Loop(l) = ast {
-            let result = replace_loops(l);
-            *ast = result;
+fn replace_patterns(code: AstCode, cells: &CellConfig) -> AstCode {
+    let map = SourceMap::unknown(&code);
+    replace_patterns_mapped(code, map, cells).0
+}
+
+/// 全てのネストの深さのループを，内側から順にパターンに合わせて置き換える
+///
+/// `[-]+++`のように，セルを0にした直後の増減は1つの`Load`にまとめる
+fn replace_patterns_mapped(
+    code: AstCode,
+    map: SourceMap,
+    cells: &CellConfig,
+) -> (AstCode, SourceMap) {
+    map_blocks(code, map, |block| {
+        let mut result: Block = Vec::with_capacity(block.len());
+        for (ast, mut node) in block {
+            let ast = match ast {
+                Ast::Loop(l) => {
+                    let ast = replace_loops(l, cells);
+                    if !matches!(ast, Ast::Loop(_)) {
+                        // 置き換えた命令はループ全体から来る
+                        node.body = SourceMap::default();
+                    }
+                    ast
+                }
+                ast => ast,
+            };
+
+            let diff = match ast {
+                Ast::InclementValue(count) => count as i128,
+                Ast::DecrementValue(count) => -(count as i128),
+                _ => 0,
+            };
+            match result.last_mut() {
+                Some((Ast::Load(n), last_node)) if diff != 0 => match cells.add(*n, diff) {
+                    Some(value) => {
+                        *n = value;
+                        last_node.merge(&node);
+                    }
+                    // 範囲外になってエラーになる増減は残す
+                    None => result.push((ast, node)),
+                },
+                _ => result.push((ast, node)),
+            }
         }
-    }
 
-    code
+        result
+    })
 }
 
-fn replace_loops(loop_code: &AstCode) -> Ast {
-    let vec = loop_code.vec();
+fn replace_loops(loop_code: AstCode, cells: &CellConfig) -> Ast {
+    let Some((offset, mut deltas)) = loop_effect(loop_code.vec()) else {
+        // 最適化パターンに合わなかった場合は何もしない
+        return Ast::Loop(loop_code);
+    };
+    let control = deltas.remove(&0).unwrap_or(0);
 
-    if *vec == vec![Ast::DecrementValue(1)] {
-        return Ast::Load(0);
+    match (offset, control) {
+        // 奇数ずつ増減するループは，折り返しのもとで必ずセルを0にして終わる
+        (0, control) if control % 2 != 0 && cells.is_wrapping() && deltas.is_empty() => {
+            Ast::Load(0)
+        }
+        // 折り返さない場合は，1ずつ減らすループだけが必ずセルを0にして終わる
+        (0, -1) if deltas.is_empty() => Ast::Load(0),
+        // 移動だけのループ
+        (1.., 0) if deltas.is_empty() => Ast::JumpZeroRight {
+            per: offset as usize,
+        },
+        (..0, 0) if deltas.is_empty() => Ast::JumpZeroLeft {
+            per: offset.unsigned_abs(),
+        },
+        (0, -1) => {
+            let targets: Vec<(isize, isize)> = deltas.into_iter().collect();
+            match targets[..] {
+                [(offset, 1)] if offset > 0 => Ast::SumRight(offset as usize),
+                [(offset, 1)] => Ast::SumLeft(offset.unsigned_abs()),
+                _ => Ast::MultiplyAdd(targets),
+            }
+        }
+        _ => Ast::Loop(loop_code),
     }
+}
 
-    if let [Ast::InclementPointer(count)] = vec[..] {
-        return Ast::JumpZeroRight { per: count };
+/// `+ - < >`だけからなるループの本体であれば，1周でのポインタの移動量と，
+/// 相対位置ごとのセルの増減を返す．増減が0のセルは含めない
+fn loop_effect(body: &[Ast]) -> Option<(isize, BTreeMap<isize, isize>)> {
+    let mut deltas: BTreeMap<isize, isize> = BTreeMap::new();
+    let mut offset: isize = 0;
+    for ast in body {
+        match ast {
+            Ast::InclementPointer(count) => offset += *count as isize,
+            Ast::DecrementPointer(count) => offset -= *count as isize,
+            Ast::InclementValue(count) => *deltas.entry(offset).or_default() += *count as isize,
+            Ast::DecrementValue(count) => *deltas.entry(offset).or_default() -= *count as isize,
+            _ => return None,
+        }
     }
+    deltas.retain(|_, delta| *delta != 0);
 
-    if let [Ast::DecrementPointer(count)] = vec[..] {
-        return Ast::JumpZeroLeft { per: count };
-    }
+    Some((offset, deltas))
+}
 
-    if let [Ast::DecrementValue(1), Ast::InclementPointer(count1), Ast::InclementValue(1), Ast::DecrementPointer(count2)] =
-        vec[..]
-    {
-        if count1 == count2 {
-            return Ast::SumRight(count1);
-        }
-    }
+/// 命令と，その元になったソースコード上の範囲の列
+type Block = Vec<(Ast, SourceNode)>;
+
+/// 内側のブロックから順に，各ブロックの命令列を`f`で書き換える
+///
+/// `f`に渡されるブロック中のループは書き換え済み．`f`は命令をまとめたら範囲もまとめる．
+/// 再帰を使わないので，深くネストしたプログラムでもスタックを消費しない
+fn map_blocks(
+    mut code: AstCode,
+    mut map: SourceMap,
+    mut f: impl FnMut(Block) -> Block,
+) -> (AstCode, SourceMap) {
+    // 処理中のブロックの残りとその範囲，処理済みの命令列，ループであればその範囲
+    let mut stack: Vec<(
+        std::vec::IntoIter<Ast>,
+        std::vec::IntoIter<SourceNode>,
+        Block,
+        SourceRange,
+    )> = vec![(
+        std::mem::take(code.vec_mut()).into_iter(),
+        std::mem::take(map.vec_mut()).into_iter(),
+        Vec::new(),
+        SourceRange::default(),
+    )];
 
-    if let [Ast::DecrementValue(1), Ast::DecrementPointer(count1), Ast::InclementValue(1), Ast::InclementPointer(count2)] =
-        vec[..]
-    {
-        if count1 == count2 {
-            return Ast::SumLeft(count1);
+    loop {
+        let (rest, nodes, done, _) = stack.last_mut().unwrap();
+        // 表の形が合わなければ，足りない分は範囲が分からないものとする
+        let node = nodes.next().unwrap_or_default();
+        match rest.next() {
+            Some(Ast::Loop(mut body)) => {
+                let SourceNode {
+                    range,
+                    body: mut body_map,
+                } = node;
+                let body = std::mem::take(body.vec_mut());
+                let body_map = std::mem::take(body_map.vec_mut());
+                stack.push((body.into_iter(), body_map.into_iter(), Vec::new(), range));
+            }
+            Some(ast) => done.push((ast, node)),
+            None => {
+                let (_, _, done, range) = stack.pop().unwrap();
+                let (block, nodes): (Vec<Ast>, Vec<SourceNode>) = f(done).into_iter().unzip();
+                let (block, map) = (AstCode::new(block), SourceMap::new(nodes));
+                match stack.last_mut() {
+                    Some((_, _, parent, _)) => {
+                        parent.push((Ast::Loop(block), SourceNode { range, body: map }))
+                    }
+                    None => return (block, map),
+                }
+            }
         }
     }
-
-    // 最適化パターンに合わなかった場合は何もしない
-    Ast::Loop(loop_code.clone())
 }
 
 #[cfg(test)]
@@ -111,6 +493,7 @@ mod tests {
     use std::vec;
 
     use super::*;
+    use crate::cell::{CellWidth, Overflow};
 
     #[test]
     fn test_run_length_optimize() {
@@ -161,7 +544,7 @@ mod tests {
         ]);
 
         assert_eq!(
-            replace_patterns(code),
+            replace_patterns(code, &CellConfig::default()),
             AstCode::new(vec![
                 Ast::Load(0),
                 Ast::Loop(AstCode::new(vec![Ast::DecrementValue(10),])),
@@ -177,4 +560,300 @@ mod tests {
             ])
         );
     }
+
+    #[test]
+    fn replace_linear_loops() {
+        let code = AstCode::new(vec![
+            // [->+++>--<<]
+            Ast::Loop(AstCode::new(vec![
+                Ast::DecrementValue(1),
+                Ast::InclementPointer(1),
+                Ast::InclementValue(3),
+                Ast::InclementPointer(1),
+                Ast::DecrementValue(2),
+                Ast::DecrementPointer(2),
+            ])),
+            // [<<+>>-]
+            Ast::Loop(AstCode::new(vec![
+                Ast::DecrementPointer(2),
+                Ast::InclementValue(1),
+                Ast::InclementPointer(2),
+                Ast::DecrementValue(1),
+            ])),
+            // [>+<--]
+            Ast::Loop(AstCode::new(vec![
+                Ast::InclementPointer(1),
+                Ast::InclementValue(1),
+                Ast::DecrementPointer(1),
+                Ast::DecrementValue(2),
+            ])),
+            // [->+<<]
+            Ast::Loop(AstCode::new(vec![
+                Ast::DecrementValue(1),
+                Ast::InclementPointer(1),
+                Ast::InclementValue(1),
+                Ast::DecrementPointer(2),
+            ])),
+        ]);
+
+        assert_eq!(
+            replace_patterns(code, &CellConfig::default()),
+            AstCode::new(vec![
+                Ast::MultiplyAdd(vec![(1, 3), (2, -2)]),
+                Ast::SumLeft(2),
+                Ast::Loop(AstCode::new(vec![
+                    Ast::InclementPointer(1),
+                    Ast::InclementValue(1),
+                    Ast::DecrementPointer(1),
+                    Ast::DecrementValue(2),
+                ])),
+                Ast::Loop(AstCode::new(vec![
+                    Ast::DecrementValue(1),
+                    Ast::InclementPointer(1),
+                    Ast::InclementValue(1),
+                    Ast::DecrementPointer(2),
+                ])),
+            ])
+        );
+    }
+
+    #[test]
+    fn replace_clear_and_scan_loops() {
+        let code = AstCode::new(vec![
+            // [+]
+            Ast::Loop(AstCode::new(vec![Ast::InclementValue(1)])),
+            // [---]
+            Ast::Loop(AstCode::new(vec![Ast::DecrementValue(3)])),
+            // [--]は奇数の値では終わらない
+            Ast::Loop(AstCode::new(vec![Ast::DecrementValue(2)])),
+            // [-]+++
+            Ast::Loop(AstCode::new(vec![Ast::DecrementValue(1)])),
+            Ast::InclementValue(3),
+            // [>>>]
+            Ast::Loop(AstCode::new(vec![Ast::InclementPointer(3)])),
+            // [>><<<+-]
+            Ast::Loop(AstCode::new(vec![
+                Ast::InclementPointer(2),
+                Ast::DecrementPointer(3),
+                Ast::InclementValue(1),
+                Ast::DecrementValue(1),
+            ])),
+            // [><]は終わらない
+            Ast::Loop(AstCode::new(vec![
+                Ast::InclementPointer(1),
+                Ast::DecrementPointer(1),
+            ])),
+        ]);
+
+        assert_eq!(
+            replace_patterns(code, &CellConfig::default()),
+            AstCode::new(vec![
+                Ast::Load(0),
+                Ast::Load(0),
+                Ast::Loop(AstCode::new(vec![Ast::DecrementValue(2)])),
+                Ast::Load(3),
+                Ast::JumpZeroRight { per: 3 },
+                Ast::JumpZeroLeft { per: 1 },
+                Ast::Loop(AstCode::new(vec![
+                    Ast::InclementPointer(1),
+                    Ast::DecrementPointer(1),
+                ])),
+            ])
+        );
+    }
+
+    #[test]
+    fn replace_nested_loops() {
+        // +[>[-]<[->+<]-]
+        let code = AstCode::new(vec![
+            Ast::InclementValue(1),
+            Ast::Loop(AstCode::new(vec![
+                Ast::InclementPointer(1),
+                Ast::Loop(AstCode::new(vec![Ast::DecrementValue(1)])),
+                Ast::DecrementPointer(1),
+                Ast::Loop(AstCode::new(vec![
+                    Ast::DecrementValue(1),
+                    Ast::InclementPointer(1),
+                    Ast::InclementValue(1),
+                    Ast::DecrementPointer(1),
+                ])),
+                Ast::DecrementValue(1),
+            ])),
+        ]);
+
+        assert_eq!(
+            replace_patterns(code, &CellConfig::default()),
+            AstCode::new(vec![
+                Ast::InclementValue(1),
+                Ast::Loop(AstCode::new(vec![
+                    Ast::InclementPointer(1),
+                    Ast::Load(0),
+                    Ast::DecrementPointer(1),
+                    Ast::SumRight(1),
+                    Ast::DecrementValue(1),
+                ])),
+            ])
+        );
+    }
+
+    #[test]
+    fn optimize_until_fixpoint() {
+        // [[-]--[-]>>[>]]
+        let code = AstCode::new(vec![Ast::Loop(AstCode::new(vec![
+            Ast::Loop(AstCode::new(vec![Ast::DecrementValue(1)])),
+            Ast::DecrementValue(1),
+            Ast::DecrementValue(1),
+            Ast::Loop(AstCode::new(vec![Ast::DecrementValue(1)])),
+            Ast::InclementPointer(1),
+            Ast::InclementPointer(1),
+            Ast::Loop(AstCode::new(vec![Ast::InclementPointer(1)])),
+        ]))]);
+
+        let optimizer = Optimizer::new().without_pass("dead-loops");
+        let (optimized, stats) = optimizer.optimize_with_stats(code);
+        assert_eq!(
+            optimized,
+            AstCode::new(vec![Ast::Loop(AstCode::new(vec![
+                Ast::Load(254),
+                Ast::Load(0),
+                Ast::InclementPointer(2),
+                Ast::JumpZeroRight { per: 1 },
+            ]))])
+        );
+        // 既定のレベルでもノード数が減らなくなるまで繰り返し，もう一度最適化しても変わらない
+        let last = stats.last().unwrap();
+        assert!(last.iteration > 1);
+        assert_eq!(last.nodes_before, last.nodes_after);
+        assert_eq!(optimizer.optimize(optimized.clone()), optimized);
+    }
+
+    #[test]
+    fn opt_levels_and_stats() {
+        // ++[-]
+        let code = AstCode::new(vec![
+            Ast::InclementValue(1),
+            Ast::InclementValue(1),
+            Ast::Loop(AstCode::new(vec![Ast::DecrementValue(1)])),
+        ]);
+
+        assert_eq!(
+            Optimizer::with_level(OptLevel::O0).optimize(code.clone()),
+            code
+        );
+        assert_eq!(
+            Optimizer::with_level(OptLevel::O1).optimize(code.clone()),
+            AstCode::new(vec![
+                Ast::InclementValue(2),
+                Ast::Loop(AstCode::new(vec![Ast::DecrementValue(1)])),
+            ])
+        );
+
+        let (optimized, stats) = Optimizer::with_level(OptLevel::O3)
+            .without_pass("dead-loops")
+            .without_pass("partial-eval")
+            .optimize_with_stats(code);
+        assert_eq!(
+            optimized,
+            AstCode::new(vec![Ast::InclementValue(2), Ast::Load(0)])
+        );
+        assert_eq!(
+            stats,
+            vec![
+                PassStats {
+                    name: "run-length",
+                    iteration: 1,
+                    nodes_before: 4,
+                    nodes_after: 3,
+                },
+                PassStats {
+                    name: "replace-loops",
+                    iteration: 1,
+                    nodes_before: 3,
+                    nodes_after: 2,
+                },
+                PassStats {
+                    name: "run-length",
+                    iteration: 2,
+                    nodes_before: 2,
+                    nodes_after: 2,
+                },
+                PassStats {
+                    name: "replace-loops",
+                    iteration: 2,
+                    nodes_before: 2,
+                    nodes_after: 2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn disable_pass_by_name() {
+        let optimizer = Optimizer::new().without_pass("run-length");
+        assert_eq!(optimizer.pass_names(), vec!["replace-loops", "dead-loops"]);
+
+        let optimizer =
+            Optimizer::with_level(OptLevel::O0).with_pass(named_pass("replace-loops").unwrap());
+        assert_eq!(optimizer.pass_names(), vec!["replace-loops"]);
+        assert!(named_pass("unknown").is_none());
+        assert_eq!("-O2".trim_start_matches('-').parse(), Ok(OptLevel::O2));
+        assert_eq!(
+            Optimizer::with_level(OptLevel::O3).pass_names(),
+            vec!["run-length", "replace-loops", "dead-loops", "partial-eval"]
+        );
+    }
+
+    #[test]
+    fn checked_cells_keep_overflowing_code() {
+        // [+][---][-]++++
+        let code = || {
+            AstCode::new(vec![
+                Ast::Loop(AstCode::new(vec![Ast::InclementValue(1)])),
+                Ast::Loop(AstCode::new(vec![Ast::DecrementValue(3)])),
+                Ast::Loop(AstCode::new(vec![Ast::DecrementValue(1)])),
+                Ast::InclementValue(300),
+            ])
+        };
+
+        assert_eq!(
+            replace_patterns(code(), &CellConfig::default()),
+            AstCode::new(vec![Ast::Load(0), Ast::Load(0), Ast::Load(44)])
+        );
+        assert_eq!(
+            replace_patterns(code(), &CellConfig::new(CellWidth::U16, Overflow::Wrapping)),
+            AstCode::new(vec![Ast::Load(0), Ast::Load(0), Ast::Load(300)])
+        );
+        assert_eq!(
+            replace_patterns(code(), &CellConfig::new(CellWidth::U8, Overflow::Checked)),
+            AstCode::new(vec![
+                Ast::Loop(AstCode::new(vec![Ast::InclementValue(1)])),
+                Ast::Loop(AstCode::new(vec![Ast::DecrementValue(3)])),
+                Ast::Load(0),
+                Ast::InclementValue(300),
+            ])
+        );
+    }
+
+    #[test]
+    fn deeply_nested_optimize() {
+        const DEPTH: usize = 1_000_000;
+        let mut code = AstCode::new(vec![Ast::DecrementValue(1), Ast::DecrementValue(1)]);
+        for _ in 0..DEPTH {
+            code = AstCode::new(vec![Ast::Loop(code), Ast::InclementValue(1)]);
+        }
+        // 最初のループが取り除かれないように，入力を読んでおく
+        code.vec_mut().insert(0, Ast::Input);
+
+        let mut code = Optimizer::new().optimize(code);
+        assert_eq!(code.vec_mut().remove(0), Ast::Input);
+
+        let mut depth = 0;
+        let mut code = &code;
+        while let [Ast::Loop(body), Ast::InclementValue(1)] = &code.vec()[..] {
+            depth += 1;
+            code = body;
+        }
+        assert_eq!(depth, DEPTH);
+        assert_eq!(code.vec(), &vec![Ast::DecrementValue(2)]);
+    }
 }
@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+
+use crate::cell::CellConfig;
+use crate::inst::{Ast, AstCode};
+use crate::source_map::{SourceMap, SourceNode};
+use crate::tape::TapeConfig;
+
+use super::{map_blocks, Block, Pass, Target};
+
+/// 値が分かっているセルを追跡して，実行されないループを取り除く
+///
+/// 現在のセルが0と分かっている位置のループ，`SumRight`などは何もしないので取り除く．
+/// `Load`の後の`InclementValue`と`DecrementValue`は1つの`Load`にまとめ，
+/// セルが既に持っている値の`Load`も取り除く
+#[derive(Debug, Clone, Copy)]
+pub struct DeadLoops;
+
+impl Pass for DeadLoops {
+    fn name(&self) -> &'static str {
+        "dead-loops"
+    }
+
+    fn run(&self, code: AstCode, target: &Target) -> AstCode {
+        let map = SourceMap::unknown(&code);
+        self.run_mapped(code, map, target).0
+    }
+
+    fn run_mapped(&self, code: AstCode, map: SourceMap, target: &Target) -> (AstCode, SourceMap) {
+        // 端が繋がったテープでは，相対位置からどのセルか決められない
+        if let TapeConfig::Circular(_) = target.tape {
+            return (code, map);
+        }
+        let config = &target.cells;
+        // ループの本体に入った時点では，現在のセルが0でないことしか分からない
+        let (mut code, mut map) = map_blocks(code, map, |block| {
+            fold_known_cells(block, Cells::unknown(), config)
+        });
+
+        // プログラムの開始時点では全てのセルが0．前のテープを引き継ぐ時は何も分からない
+        let entry = if target.zeroed_entry {
+            Cells::zeroed()
+        } else {
+            Cells::unknown()
+        };
+        let block: Block = std::mem::take(code.vec_mut())
+            .into_iter()
+            .zip(std::mem::take(map.vec_mut()))
+            .collect();
+        let (block, nodes): (Vec<Ast>, Vec<SourceNode>) =
+            fold_known_cells(block, entry, config).into_iter().unzip();
+        (AstCode::new(block), SourceMap::new(nodes))
+    }
+}
+
+/// ブロックの先頭からの相対位置で表した，値が分かっているセル
+#[derive(Debug)]
+struct Cells {
+    /// ブロックの先頭からのポインタの移動量
+    pointer: isize,
+    /// 値が分かっているセルと，値が分からなくなったセル
+    known: HashMap<isize, Option<u32>>,
+    /// `known`にないセルが0か
+    rest_zero: bool,
+}
+
+impl Cells {
+    fn zeroed() -> Self {
+        Self {
+            pointer: 0,
+            known: HashMap::new(),
+            rest_zero: true,
+        }
+    }
+
+    fn unknown() -> Self {
+        Self {
+            rest_zero: false,
+            ..Self::zeroed()
+        }
+    }
+
+    fn get(&self, offset: isize) -> Option<u32> {
+        match self.known.get(&(self.pointer + offset)) {
+            Some(&value) => value,
+            None => self.rest_zero.then_some(0),
+        }
+    }
+
+    fn set(&mut self, offset: isize, value: Option<u32>) {
+        self.known.insert(self.pointer + offset, value);
+    }
+
+    /// ループの後は，現在のセルが0であることだけを残す
+    fn forget_except_current_zero(&mut self) {
+        *self = Self::unknown();
+        self.set(0, Some(0));
+    }
+}
+
+fn fold_known_cells(block: Block, mut cells: Cells, config: &CellConfig) -> Block {
+    let mut result: Block = Vec::with_capacity(block.len());
+
+    for (ast, node) in block {
+        match ast {
+            Ast::InclementPointer(count) => {
+                cells.pointer += count as isize;
+                result.push((ast, node));
+            }
+            Ast::DecrementPointer(count) => {
+                cells.pointer -= count as isize;
+                result.push((ast, node));
+            }
+            Ast::InclementValue(count) | Ast::DecrementValue(count) => {
+                let diff = if let Ast::InclementValue(_) = ast {
+                    count as i128
+                } else {
+                    -(count as i128)
+                };
+                let value = cells.get(0).and_then(|value| config.add(value, diff));
+                cells.set(0, value);
+
+                match result.last_mut() {
+                    Some((Ast::Load(n), last_node)) if config.add(*n, diff).is_some() => {
+                        *n = config.add(*n, diff).unwrap();
+                        last_node.merge(&node);
+                    }
+                    _ => result.push((ast, node)),
+                }
+            }
+            Ast::Load(n) => {
+                if cells.get(0) == Some(n) {
+                    continue;
+                }
+                // 直前の値の書き換えは上書きされる．折り返さない場合は増減がエラーになり得るので残す
+                while let Some((last, _)) = result.last() {
+                    let overwritten = match last {
+                        Ast::Load(_) => true,
+                        Ast::InclementValue(_) | Ast::DecrementValue(_) => config.is_wrapping(),
+                        _ => false,
+                    };
+                    if !overwritten {
+                        break;
+                    }
+                    result.pop();
+                }
+                cells.set(0, Some(n));
+                result.push((ast, node));
+            }
+            Ast::Input => {
+                cells.set(0, None);
+                result.push((ast, node));
+            }
+            Ast::Output => result.push((ast, node)),
+            Ast::Loop(_) | Ast::JumpZeroRight { .. } | Ast::JumpZeroLeft { .. } => {
+                if cells.get(0) == Some(0) {
+                    continue;
+                }
+                cells.forget_except_current_zero();
+                result.push((ast, node));
+            }
+            Ast::SumRight(count) => {
+                if multiply_add(&mut cells, &[(count as isize, 1)], config) {
+                    result.push((ast, node));
+                }
+            }
+            Ast::SumLeft(count) => {
+                if multiply_add(&mut cells, &[(-(count as isize), 1)], config) {
+                    result.push((ast, node));
+                }
+            }
+            Ast::MultiplyAdd(ref targets) => {
+                if multiply_add(&mut cells, targets, config) {
+                    result.push((ast, node));
+                }
+            }
+        }
+    }
+
+    result
+}
+
+/// 掛けて加えた後のセルの値を求める．現在のセルが0で何もしなければ`false`を返す
+fn multiply_add(cells: &mut Cells, targets: &[(isize, isize)], config: &CellConfig) -> bool {
+    let value = cells.get(0);
+    if value == Some(0) {
+        return false;
+    }
+
+    for &(offset, factor) in targets {
+        let target = value
+            .zip(cells.get(offset))
+            .and_then(|(value, target)| config.multiply_add(target, value, factor));
+        cells.set(offset, target);
+    }
+    cells.set(0, Some(0));
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_loops_on_zero_cells() {
+        // [>+<-]+[-][.]>,[-][>]
+        let code = AstCode::new(vec![
+            Ast::Loop(AstCode::new(vec![Ast::InclementPointer(1)])),
+            Ast::InclementValue(1),
+            Ast::Loop(AstCode::new(vec![Ast::DecrementValue(1)])),
+            Ast::Loop(AstCode::new(vec![Ast::Output])),
+            Ast::InclementPointer(1),
+            Ast::Input,
+            Ast::Loop(AstCode::new(vec![Ast::DecrementValue(1)])),
+            Ast::JumpZeroRight { per: 1 },
+        ]);
+
+        assert_eq!(
+            DeadLoops.run(code, &Target::default()),
+            AstCode::new(vec![
+                Ast::InclementValue(1),
+                Ast::Loop(AstCode::new(vec![Ast::DecrementValue(1)])),
+                Ast::InclementPointer(1),
+                Ast::Input,
+                Ast::Loop(AstCode::new(vec![Ast::DecrementValue(1)])),
+            ])
+        );
+    }
+
+    #[test]
+    fn keeps_loops_with_unknown_entry() {
+        // [.-]>[-]
+        let code = AstCode::new(vec![
+            Ast::Loop(AstCode::new(vec![Ast::Output, Ast::DecrementValue(1)])),
+            Ast::InclementPointer(1),
+            Ast::Loop(AstCode::new(vec![Ast::DecrementValue(1)])),
+        ]);
+        let target = Target {
+            zeroed_entry: false,
+            ..Target::default()
+        };
+
+        assert_eq!(DeadLoops.run(code.clone(), &target), code);
+    }
+
+    #[test]
+    fn folds_loads() {
+        // ,[>,Load(3)+++.Load(0)[->+<]<++Load(0)]
+        let code = AstCode::new(vec![
+            Ast::Input,
+            Ast::Loop(AstCode::new(vec![
+                Ast::InclementPointer(1),
+                Ast::Input,
+                Ast::Load(3),
+                Ast::InclementValue(3),
+                Ast::Output,
+                Ast::Load(0),
+                Ast::SumRight(1),
+                Ast::DecrementPointer(1),
+                Ast::InclementValue(2),
+                Ast::Load(0),
+            ])),
+        ]);
+
+        assert_eq!(
+            DeadLoops.run(code, &Target::default()),
+            AstCode::new(vec![
+                Ast::Input,
+                Ast::Loop(AstCode::new(vec![
+                    Ast::InclementPointer(1),
+                    Ast::Input,
+                    Ast::Load(6),
+                    Ast::Output,
+                    Ast::Load(0),
+                    Ast::DecrementPointer(1),
+                    Ast::Load(0),
+                ])),
+            ])
+        );
+    }
+}
@@ -0,0 +1,505 @@
+use crate::cell::CellConfig;
+use crate::inst::{Ast, AstCode};
+use crate::source_map::{SourceMap, SourceNode};
+use crate::tape::TapeConfig;
+
+use super::{Pass, Target};
+
+/// 入力を読む前の部分を，全て0のテープから実行した結果に置き換える
+///
+/// 最初の`Input`か燃料切れまでに実行できた最上位の命令を，出力するバイトの`Load`と`Output`，
+/// 変化したセルの`Load`，最後のポインタの移動に置き換える．
+/// 入力を読まずに最後まで実行できたプログラムは，定数の出力と最後のテープだけになる
+#[derive(Debug, Clone, Copy)]
+pub struct PartialEval {
+    /// 実行する命令の数の上限
+    pub fuel: usize,
+}
+
+impl Default for PartialEval {
+    fn default() -> Self {
+        Self { fuel: 1_000_000 }
+    }
+}
+
+impl Pass for PartialEval {
+    fn name(&self) -> &'static str {
+        "partial-eval"
+    }
+
+    fn run(&self, code: AstCode, target: &Target) -> AstCode {
+        let map = SourceMap::unknown(&code);
+        self.run_mapped(code, map, target).0
+    }
+
+    fn run_mapped(
+        &self,
+        mut code: AstCode,
+        mut map: SourceMap,
+        target: &Target,
+    ) -> (AstCode, SourceMap) {
+        // 開始時のテープか，離れた相対位置が同じセルかどうかが分からなければ実行できない
+        let len = match target.tape {
+            TapeConfig::Fixed(len) => len,
+            // 0より左には動かないので，伸ばした長さは最後に使ったセルまでになる
+            TapeConfig::GrowRight(_) | TapeConfig::Unbounded => target.max_tape_len,
+            TapeConfig::Circular(_) => return (code, map),
+        };
+        if !target.zeroed_entry {
+            return (code, map);
+        }
+
+        let mut state = State {
+            fuel: self.fuel,
+            cells: target.cells,
+            len,
+            ..State::default()
+        };
+
+        // 途中で止まった命令の影響を捨てられるように，最上位の命令ごとに状態を確定させる
+        let mut evaluated = 0;
+        for ast in code.vec() {
+            let mark = state.mark();
+            if !state.run(ast) {
+                state.rollback(mark);
+                break;
+            }
+            evaluated += 1;
+        }
+
+        let rest = code.vec_mut().split_off(evaluated);
+        let mut result = state.to_ast();
+        let generated = result.len();
+        result.extend(rest);
+
+        // 実行した命令から作った命令は，実行した部分の全体から来る
+        let evaluated = evaluated.min(map.vec().len());
+        let rest = map.vec_mut().split_off(evaluated);
+        let node = SourceNode::new(map.range());
+        let mut nodes = vec![node; generated];
+        nodes.extend(rest);
+
+        (AstCode::new(result), SourceMap::new(nodes))
+    }
+}
+
+/// 実行中のテープと出力
+#[derive(Debug, Clone, Default)]
+struct State {
+    tape: Vec<u32>,
+    pointer: usize,
+    output: Vec<u32>,
+    fuel: usize,
+    cells: CellConfig,
+    /// テープの長さか，伸びるテープの長さの上限
+    len: usize,
+    /// 最後の`mark`から書き換えたセルの添字と前の値
+    undo: Vec<(usize, u32)>,
+}
+
+/// `State::rollback`で戻る位置
+#[derive(Debug, Clone, Copy)]
+struct Mark {
+    pointer: usize,
+    output_len: usize,
+}
+
+impl State {
+    /// ここまでの変更を確定し，戻る位置を返す
+    fn mark(&mut self) -> Mark {
+        self.undo.clear();
+        Mark {
+            pointer: self.pointer,
+            output_len: self.output.len(),
+        }
+    }
+
+    /// 最後の`mark`の後の変更を取り消す
+    fn rollback(&mut self, mark: Mark) {
+        for (index, value) in self.undo.drain(..).rev() {
+            self.tape[index] = value;
+        }
+        self.pointer = mark.pointer;
+        self.output.truncate(mark.output_len);
+    }
+
+    /// 命令を最後まで実行する．入力，燃料切れ，テープの範囲外への移動，
+    /// 折り返さない場合のセルの範囲外への増減があれば`false`を返す
+    fn run(&mut self, ast: &Ast) -> bool {
+        // 実行中のブロックと，次に実行する命令の位置
+        let mut stack: Vec<(&[Ast], usize)> = vec![(std::slice::from_ref(ast), 0)];
+
+        loop {
+            let in_loop = stack.len() > 1;
+            let Some((block, index)) = stack.last_mut() else {
+                return true;
+            };
+            if !self.burn() {
+                return false;
+            }
+
+            let Some(ast) = block.get(*index) else {
+                // ループの本体の終わり．最上位のブロックは1周しかしない
+                if in_loop && self.cell(0) != 0 {
+                    *index = 0;
+                } else {
+                    stack.pop();
+                }
+                continue;
+            };
+            *index += 1;
+
+            match ast {
+                Ast::InclementPointer(count) => {
+                    if !self.move_pointer(*count as isize) {
+                        return false;
+                    }
+                }
+                Ast::DecrementPointer(count) => {
+                    if !self.move_pointer(-(*count as isize)) {
+                        return false;
+                    }
+                }
+                Ast::InclementValue(count) => {
+                    if !self.add(0, *count as i128) {
+                        return false;
+                    }
+                }
+                Ast::DecrementValue(count) => {
+                    if !self.add(0, -(*count as i128)) {
+                        return false;
+                    }
+                }
+                Ast::Output => self.output.push(self.cell(0)),
+                Ast::Input => return false,
+                Ast::Loop(body) => {
+                    if self.cell(0) != 0 {
+                        stack.push((body.vec(), 0));
+                    }
+                }
+                Ast::Load(n) => self.set(*n),
+                Ast::SumRight(count) => {
+                    if !self.multiply_add(&[(*count as isize, 1)]) {
+                        return false;
+                    }
+                }
+                Ast::SumLeft(count) => {
+                    if !self.multiply_add(&[(-(*count as isize), 1)]) {
+                        return false;
+                    }
+                }
+                Ast::MultiplyAdd(targets) => {
+                    if !self.multiply_add(targets) {
+                        return false;
+                    }
+                }
+                // 調べたセルごとに燃料を使う
+                Ast::JumpZeroRight { per } => {
+                    while self.cell(0) != 0 {
+                        if !self.burn() || !self.move_pointer(*per as isize) {
+                            return false;
+                        }
+                    }
+                }
+                Ast::JumpZeroLeft { per } => {
+                    while self.cell(0) != 0 {
+                        if !self.burn() || !self.move_pointer(-(*per as isize)) {
+                            return false;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// 燃料を1つ使う．残っていなければ`false`を返す
+    fn burn(&mut self) -> bool {
+        let Some(fuel) = self.fuel.checked_sub(1) else {
+            return false;
+        };
+        self.fuel = fuel;
+        true
+    }
+
+    /// 現在のセルから相対位置`offset`にあるセルの添字．テープの範囲外なら`None`
+    fn index(&self, offset: isize) -> Option<usize> {
+        self.pointer
+            .checked_add_signed(offset)
+            .filter(|&index| index < self.len)
+    }
+
+    /// テープの範囲外に出るなら`false`を返す
+    fn move_pointer(&mut self, diff: isize) -> bool {
+        match self.index(diff) {
+            Some(pointer) => {
+                self.pointer = pointer;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn cell(&self, offset: isize) -> u32 {
+        let index = self.pointer.wrapping_add_signed(offset);
+        self.tape.get(index).copied().unwrap_or(0)
+    }
+
+    fn cell_mut(&mut self, offset: isize) -> &mut u32 {
+        let index = self.pointer.wrapping_add_signed(offset);
+        if self.tape.len() <= index {
+            self.tape.resize(index + 1, 0);
+        }
+        self.undo.push((index, self.tape[index]));
+        &mut self.tape[index]
+    }
+
+    /// 範囲外になって増減できなければ`false`を返す
+    fn add(&mut self, offset: isize, diff: i128) -> bool {
+        let cells = self.cells;
+        let cell = self.cell_mut(offset);
+        match cells.add(*cell, diff) {
+            Some(value) => {
+                *cell = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn set(&mut self, n: u32) {
+        *self.cell_mut(0) = n;
+    }
+
+    fn multiply_add(&mut self, targets: &[(isize, isize)]) -> bool {
+        let value = self.cell(0);
+        if value == 0 {
+            return true;
+        }
+        if targets
+            .iter()
+            .any(|&(offset, _)| self.index(offset).is_none())
+        {
+            return false;
+        }
+
+        for &(offset, factor) in targets {
+            if !self.add(offset, value as i128 * factor as i128) {
+                return false;
+            }
+        }
+        self.set(0);
+        true
+    }
+
+    /// 全て0のテープからこの状態を作る命令列
+    fn to_ast(&self) -> Vec<Ast> {
+        let mut result = Vec::new();
+
+        // 出力は0番目のセルを使って書き出す
+        let mut current = 0;
+        for &byte in self.output.iter() {
+            if byte != current {
+                result.push(Ast::Load(byte));
+                current = byte;
+            }
+            result.push(Ast::Output);
+        }
+
+        let first = self.tape.first().copied().unwrap_or(0);
+        if current != first {
+            result.push(Ast::Load(first));
+        }
+        let mut position = 0;
+        for (index, &value) in self.tape.iter().enumerate().skip(1) {
+            if value != 0 {
+                result.push(Ast::InclementPointer(index - position));
+                result.push(Ast::Load(value));
+                position = index;
+            }
+        }
+        match self.pointer.cmp(&position) {
+            std::cmp::Ordering::Greater => {
+                result.push(Ast::InclementPointer(self.pointer - position))
+            }
+            std::cmp::Ordering::Less => result.push(Ast::DecrementPointer(position - self.pointer)),
+            std::cmp::Ordering::Equal => {}
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_program_becomes_print() {
+        // ++++++++[>+++++++++<-]>..+.[-]
+        let code = AstCode::new(vec![
+            Ast::InclementValue(8),
+            Ast::Loop(AstCode::new(vec![
+                Ast::InclementPointer(1),
+                Ast::InclementValue(9),
+                Ast::DecrementPointer(1),
+                Ast::DecrementValue(1),
+            ])),
+            Ast::InclementPointer(1),
+            Ast::Output,
+            Ast::Output,
+            Ast::InclementValue(1),
+            Ast::Output,
+            Ast::Load(0),
+        ]);
+
+        assert_eq!(
+            PartialEval::default().run(code, &Target::default()),
+            AstCode::new(vec![
+                Ast::Load(b'H' as u32),
+                Ast::Output,
+                Ast::Output,
+                Ast::Load(b'I' as u32),
+                Ast::Output,
+                Ast::Load(0),
+                Ast::InclementPointer(1),
+            ])
+        );
+    }
+
+    #[test]
+    fn keeps_final_tape_of_finished_program() {
+        // -
+        let code = AstCode::new(vec![Ast::DecrementValue(1)]);
+        assert_eq!(
+            PartialEval::default().run(code, &Target::default()),
+            AstCode::new(vec![Ast::Load(255)])
+        );
+    }
+
+    #[test]
+    fn stops_at_first_input() {
+        // +++>>++.<,[-]
+        let code = AstCode::new(vec![
+            Ast::InclementValue(3),
+            Ast::InclementPointer(2),
+            Ast::InclementValue(2),
+            Ast::Output,
+            Ast::DecrementPointer(1),
+            Ast::Input,
+            Ast::Load(0),
+        ]);
+
+        assert_eq!(
+            PartialEval::default().run(code, &Target::default()),
+            AstCode::new(vec![
+                Ast::Load(2),
+                Ast::Output,
+                Ast::Load(3),
+                Ast::InclementPointer(2),
+                Ast::Load(2),
+                Ast::DecrementPointer(1),
+                Ast::Input,
+                Ast::Load(0),
+            ])
+        );
+    }
+
+    #[test]
+    fn keeps_node_that_runs_out_of_fuel() {
+        // +[+]>+[]
+        let infinite = Ast::Loop(AstCode::new(vec![]));
+        let code = AstCode::new(vec![
+            Ast::InclementValue(1),
+            Ast::Loop(AstCode::new(vec![Ast::InclementValue(1)])),
+            Ast::InclementPointer(1),
+            Ast::InclementValue(1),
+            infinite.clone(),
+        ]);
+
+        assert_eq!(
+            PartialEval { fuel: 1000 }.run(code, &Target::default()),
+            AstCode::new(vec![Ast::InclementPointer(1), Ast::Load(1), infinite,])
+        );
+    }
+
+    #[test]
+    fn rolls_back_stopped_node() {
+        // +[.>+++,]
+        let stopped = Ast::Loop(AstCode::new(vec![
+            Ast::Output,
+            Ast::InclementPointer(1),
+            Ast::InclementValue(3),
+            Ast::Input,
+        ]));
+        let code = AstCode::new(vec![Ast::InclementValue(1), stopped.clone()]);
+
+        assert_eq!(
+            PartialEval::default().run(code, &Target::default()),
+            AstCode::new(vec![Ast::Load(1), stopped])
+        );
+    }
+
+    #[test]
+    fn scans_use_fuel() {
+        // +>+>+<<[>]
+        let scan = Ast::JumpZeroRight { per: 1 };
+        let code = AstCode::new(vec![
+            Ast::InclementValue(1),
+            Ast::InclementPointer(1),
+            Ast::InclementValue(1),
+            Ast::InclementPointer(1),
+            Ast::InclementValue(1),
+            Ast::DecrementPointer(2),
+            scan.clone(),
+        ]);
+
+        // 前の6個の命令に12，[>]に命令の2と3つのセルの分を使う
+        let evaluated = PartialEval { fuel: 16 }.run(code.clone(), &Target::default());
+        assert_eq!(evaluated.vec().last(), Some(&scan));
+        let evaluated = PartialEval { fuel: 17 }.run(code, &Target::default());
+        assert!(!evaluated.vec().contains(&scan));
+    }
+
+    #[test]
+    fn stops_at_tape_end() {
+        // +[->>>+<<<]>>>
+        let code = AstCode::new(vec![
+            Ast::InclementValue(1),
+            Ast::MultiplyAdd(vec![(3, 1)]),
+            Ast::InclementPointer(3),
+        ]);
+        let target = |tape| Target {
+            tape,
+            ..Target::default()
+        };
+
+        assert_eq!(
+            PartialEval::default().run(code.clone(), &target(TapeConfig::Fixed(3))),
+            AstCode::new(vec![
+                Ast::Load(1),
+                Ast::MultiplyAdd(vec![(3, 1)]),
+                Ast::InclementPointer(3)
+            ])
+        );
+        assert_eq!(
+            PartialEval::default().run(code.clone(), &target(TapeConfig::Fixed(4))),
+            AstCode::new(vec![Ast::InclementPointer(3), Ast::Load(1)])
+        );
+        assert_eq!(
+            PartialEval::default().run(code.clone(), &target(TapeConfig::GrowRight(1))),
+            AstCode::new(vec![Ast::InclementPointer(3), Ast::Load(1)])
+        );
+        let limited = Target {
+            max_tape_len: 3,
+            ..target(TapeConfig::GrowRight(1))
+        };
+        assert_eq!(
+            PartialEval::default().run(code, &limited),
+            AstCode::new(vec![
+                Ast::Load(1),
+                Ast::MultiplyAdd(vec![(3, 1)]),
+                Ast::InclementPointer(3)
+            ])
+        );
+    }
+}
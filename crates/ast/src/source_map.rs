@@ -0,0 +1,161 @@
+use crate::inst::{Ast, AstCode};
+
+/// ノードの元になったトークンの範囲．`start..end`はトークンの添字で，空であれば分からない
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct SourceRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl SourceRange {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// 1つのトークンの範囲
+    pub fn token(index: usize) -> Self {
+        Self::new(index, index + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+
+    /// 両方を含む最小の範囲．空の範囲は無視する
+    pub fn merge(self, other: Self) -> Self {
+        match (self.is_empty(), other.is_empty()) {
+            (_, true) => self,
+            (true, false) => other,
+            (false, false) => Self::new(self.start.min(other.start), self.end.max(other.end)),
+        }
+    }
+
+    /// 最初のトークン．ループであれば`[`
+    pub fn first(self) -> Self {
+        if self.is_empty() {
+            return self;
+        }
+        Self::token(self.start)
+    }
+
+    /// 最後のトークン．ループであれば`]`
+    pub fn last(self) -> Self {
+        if self.is_empty() {
+            return self;
+        }
+        Self::token(self.end - 1)
+    }
+}
+
+/// ノードの範囲と，ループであれば本体の各ノードの範囲
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceNode {
+    pub range: SourceRange,
+    pub body: SourceMap,
+}
+
+impl SourceNode {
+    pub fn new(range: SourceRange) -> Self {
+        Self {
+            range,
+            body: SourceMap::default(),
+        }
+    }
+
+    /// 隣のノードをまとめたノードの範囲にする
+    pub fn merge(&mut self, other: &SourceNode) {
+        self.range = self.range.merge(other.range);
+    }
+}
+
+/// `AstCode`と同じ形で，各ノードの元になったトークンの範囲を持つ表
+///
+/// 最適化と`OpCode`への変換はノードをまとめたり置き換えたりするので，
+/// `AstCode`と一緒に書き換えて対応を保つ
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap(Vec<SourceNode>);
+
+impl SourceMap {
+    pub fn new(nodes: Vec<SourceNode>) -> Self {
+        Self(nodes)
+    }
+
+    pub fn vec(&self) -> &Vec<SourceNode> {
+        &self.0
+    }
+
+    pub fn vec_mut(&mut self) -> &mut Vec<SourceNode> {
+        &mut self.0
+    }
+
+    /// 全てのノードの範囲を合わせた範囲
+    pub fn range(&self) -> SourceRange {
+        self.0.iter().fold(SourceRange::default(), |range, node| {
+            range.merge(node.range)
+        })
+    }
+
+    /// 全てのノードの範囲が分からない表
+    pub fn unknown(code: &AstCode) -> Self {
+        Self::filled(code, SourceRange::default())
+    }
+
+    /// 全てのノードが`range`から来た表．ノードの対応を追わないパスが使う
+    pub fn filled(code: &AstCode, range: SourceRange) -> Self {
+        // 処理中のブロックの残りと，作った範囲の列
+        let mut stack: Vec<(std::slice::Iter<Ast>, Vec<SourceNode>)> =
+            vec![(code.vec().iter(), Vec::new())];
+
+        loop {
+            let (rest, done) = stack.last_mut().unwrap();
+            match rest.next() {
+                Some(Ast::Loop(body)) => stack.push((body.vec().iter(), Vec::new())),
+                Some(_) => done.push(SourceNode::new(range)),
+                None => {
+                    let (_, done) = stack.pop().unwrap();
+                    let map = SourceMap(done);
+                    match stack.last_mut() {
+                        Some((_, parent)) => parent.push(SourceNode { range, body: map }),
+                        None => return map,
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for SourceMap {
+    /// 深くネストしたループの表を再帰せずに解放する
+    fn drop(&mut self) {
+        let mut stack = std::mem::take(&mut self.0);
+        while let Some(mut node) = stack.pop() {
+            stack.append(&mut node.body.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_ignores_unknown_ranges() {
+        let a = SourceRange::new(2, 4);
+        assert_eq!(a.merge(SourceRange::new(6, 7)), SourceRange::new(2, 7));
+        assert_eq!(a.merge(SourceRange::default()), a);
+        assert_eq!(SourceRange::default().merge(a), a);
+        assert_eq!(a.last(), SourceRange::token(3));
+    }
+
+    #[test]
+    fn deeply_nested_map() {
+        const DEPTH: usize = 1_000_000;
+        let mut code = AstCode::new(vec![Ast::InclementValue(1)]);
+        for _ in 0..DEPTH {
+            code = AstCode::new(vec![Ast::Loop(code)]);
+        }
+
+        let map = SourceMap::filled(&code, SourceRange::token(0));
+        assert_eq!(map.range(), SourceRange::token(0));
+    }
+}
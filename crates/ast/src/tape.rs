@@ -0,0 +1,77 @@
+use std::{fmt::Display, str::FromStr};
+
+/// 伸びるテープの長さの上限の既定値．`1 << 24`セルを越えて伸ばそうとすると実行時エラーになる
+pub const DEFAULT_MAX_LEN: usize = 1 << 24;
+
+/// テープの形と，範囲外に移動した時の扱い．最適化とインタプリタで同じように扱う
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapeConfig {
+    /// 長さが決まったテープ．範囲外に出るとエラーになる
+    Fixed(usize),
+    /// 右に移動すると必要なだけ伸びるテープ．0より左に出るとエラーになる
+    GrowRight(usize),
+    /// 左右どちらにも伸びるテープ．0より左のセルも使える
+    Unbounded,
+    /// 端が反対側の端に繋がった長さが決まったテープ
+    Circular(usize),
+}
+
+impl Default for TapeConfig {
+    fn default() -> Self {
+        TapeConfig::Fixed(30000)
+    }
+}
+
+impl FromStr for TapeConfig {
+    type Err = String;
+
+    /// `fixed`，`fixed:N`，`grow`，`grow:N`，`unbounded`，`circular`，`circular:N`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, len) = match s.split_once(':') {
+            Some((name, len)) => {
+                let len = len
+                    .parse()
+                    .ok()
+                    .filter(|&len| len > 0)
+                    .ok_or_else(|| format!("invalid tape length: {}", len))?;
+                (name, Some(len))
+            }
+            None => (s, None),
+        };
+        let default_len = 30000;
+
+        match (name, len) {
+            ("fixed", len) => Ok(TapeConfig::Fixed(len.unwrap_or(default_len))),
+            ("grow", len) => Ok(TapeConfig::GrowRight(len.unwrap_or(default_len))),
+            ("unbounded", None) => Ok(TapeConfig::Unbounded),
+            ("circular", len) => Ok(TapeConfig::Circular(len.unwrap_or(default_len))),
+            _ => Err(format!("unknown tape: {}", s)),
+        }
+    }
+}
+
+impl Display for TapeConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TapeConfig::Fixed(len) => write!(f, "fixed:{}", len),
+            TapeConfig::GrowRight(len) => write!(f, "grow:{}", len),
+            TapeConfig::Unbounded => write!(f, "unbounded"),
+            TapeConfig::Circular(len) => write!(f, "circular:{}", len),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        assert_eq!("fixed".parse(), Ok(TapeConfig::Fixed(30000)));
+        assert_eq!("grow:8".parse(), Ok(TapeConfig::GrowRight(8)));
+        assert_eq!("unbounded".parse(), Ok(TapeConfig::Unbounded));
+        assert_eq!("circular:256".parse(), Ok(TapeConfig::Circular(256)));
+        assert!("circular:0".parse::<TapeConfig>().is_err());
+        assert!("ring".parse::<TapeConfig>().is_err());
+    }
+}
@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+/// 実行できる量の残り．`None`は制限なし
+///
+/// `Interpreter::run`が使った分だけ減らすので，増やしてから`run`を呼べば続きから実行できる
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Budget {
+    /// 実行できる命令の数
+    pub steps: Option<usize>,
+    /// 出力できるバイト数
+    pub output: Option<usize>,
+    /// 実行できる時間
+    pub time: Option<Duration>,
+}
+
+impl Budget {
+    /// 制限なし
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    pub fn with_steps(mut self, steps: usize) -> Self {
+        self.steps = Some(steps);
+        self
+    }
+
+    pub fn with_output(mut self, output: usize) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    pub fn with_time(mut self, time: Duration) -> Self {
+        self.time = Some(time);
+        self
+    }
+}
+
+/// 使い切った制限
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Steps,
+    Output,
+    Time,
+}
+
+/// `Interpreter::run`が止まった理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// 全ての命令を実行し終えた
+    Finished,
+    /// 制限に達したので，次の命令を実行する前に止まった
+    LimitReached(Limit),
+}
@@ -0,0 +1,341 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::io::{self, Read, Write};
+
+use ast::cell::{CellConfig, CellWidth, Overflow};
+use ast::eof::EofPolicy;
+use ast::inst::{Op, OpCode};
+
+use crate::tape::{Tape, TapeConfig};
+
+/// ファイルの先頭に置く印
+const MAGIC: &[u8; 4] = b"BFCP";
+/// 形式を変えたら増やす．読めるのはこの版だけ
+pub const VERSION: u16 = 1;
+
+/// 実行を途中から続けるための状態
+///
+/// 数値は全てリトルエンディアンで，印，版，`code_hash`，`ip`，`input_position`，
+/// `output_position`，セルのビット幅，折り返し，`EofPolicy`，テープの形，ポインタ，
+/// `origin`，セルの数とセルの順に並べる
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    /// 実行していたコードの`code_hash`．違うコードでは続けられない
+    pub code_hash: u64,
+    pub ip: usize,
+    /// 読んだ入力のバイト数
+    pub input_position: u64,
+    /// 書いた出力のバイト数
+    pub output_position: u64,
+    /// 実行していたセルの幅と折り返し．テープの形と同じく，違う設定では続けられない
+    pub cells: CellConfig,
+    pub eof: EofPolicy,
+    pub tape: Tape,
+}
+
+/// 保存した状態を読めなかった理由
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    /// 先頭の印が違う
+    NotCheckpoint,
+    /// 読めない版
+    UnsupportedVersion(u16),
+    /// 値が範囲外か，テープが形に合わない
+    Corrupted,
+    /// 保存した時とコードが違う
+    CodeMismatch,
+    /// 保存した時と設定が違う．違う設定の名前を持つ
+    SettingsMismatch(&'static str),
+}
+
+impl From<io::Error> for CheckpointError {
+    fn from(value: io::Error) -> Self {
+        CheckpointError::Io(value)
+    }
+}
+
+impl Error for CheckpointError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CheckpointError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for CheckpointError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckpointError::Io(e) => write!(f, "I/O error: {}", e),
+            CheckpointError::NotCheckpoint => write!(f, "not a checkpoint file"),
+            CheckpointError::UnsupportedVersion(version) => {
+                write!(f, "unsupported checkpoint version: {}", version)
+            }
+            CheckpointError::Corrupted => write!(f, "corrupted checkpoint"),
+            CheckpointError::CodeMismatch => {
+                write!(f, "checkpoint was saved from a different program")
+            }
+            CheckpointError::SettingsMismatch(setting) => {
+                write!(f, "checkpoint was saved with a different {}", setting)
+            }
+        }
+    }
+}
+
+/// 保存した状態と同じコードかを確かめるためのハッシュ
+///
+/// コンパイラやプラットフォームで変わらないように，各命令を種類を表す1バイトと
+/// リトルエンディアンの64ビットの引数に並べて，FNV-1aで求める
+pub fn code_hash(code: &OpCode) -> u64 {
+    let mut hash = Fnv1a::default();
+    for op in code.vec() {
+        match *op {
+            Op::InclementPointer(count) => hash.op(0, &[count as u64]),
+            Op::DecrementPointer(count) => hash.op(1, &[count as u64]),
+            Op::InclementValue(count) => hash.op(2, &[count as u64]),
+            Op::DecrementValue(count) => hash.op(3, &[count as u64]),
+            Op::Output => hash.op(4, &[]),
+            Op::Input => hash.op(5, &[]),
+            Op::LoopStart { if_zero_add } => hash.op(6, &[if_zero_add as u64]),
+            Op::LoopEnd { if_non_zero_sub } => hash.op(7, &[if_non_zero_sub as u64]),
+            Op::Load(n) => hash.op(8, &[n as u64]),
+            Op::SumRight(count) => hash.op(9, &[count as u64]),
+            Op::SumLeft(count) => hash.op(10, &[count as u64]),
+            Op::MultiplyAdd(ref targets) => {
+                hash.op(11, &[targets.len() as u64]);
+                for &(offset, factor) in targets {
+                    hash.u64(offset as i64 as u64);
+                    hash.u64(factor as i64 as u64);
+                }
+            }
+            Op::JumpZeroRight { per } => hash.op(12, &[per as u64]),
+            Op::JumpZeroLeft { per } => hash.op(13, &[per as u64]),
+            Op::InclementValueAt { offset, count } => {
+                hash.op(14, &[offset as i64 as u64, count as u64])
+            }
+            Op::DecrementValueAt { offset, count } => {
+                hash.op(15, &[offset as i64 as u64, count as u64])
+            }
+            Op::OutputAt { offset } => hash.op(16, &[offset as i64 as u64]),
+            Op::InputAt { offset } => hash.op(17, &[offset as i64 as u64]),
+            Op::LoadAt { offset, n } => hash.op(18, &[offset as i64 as u64, n as u64]),
+        }
+    }
+    hash.0
+}
+
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn u64(&mut self, n: u64) {
+        self.write(&n.to_le_bytes());
+    }
+
+    /// 種類が`tag`で引数が`args`の命令
+    fn op(&mut self, tag: u8, args: &[u64]) {
+        self.write(&[tag]);
+        for &n in args {
+            self.u64(n);
+        }
+    }
+}
+
+impl Checkpoint {
+    pub fn write_to(&self, mut write: impl Write) -> io::Result<()> {
+        let (tag, len) = match self.tape.config() {
+            TapeConfig::Fixed(len) => (0, len),
+            TapeConfig::GrowRight(len) => (1, len),
+            TapeConfig::Unbounded => (2, 0),
+            TapeConfig::Circular(len) => (3, len),
+        };
+
+        write.write_all(MAGIC)?;
+        write.write_all(&VERSION.to_le_bytes())?;
+        for n in [
+            self.code_hash,
+            self.ip as u64,
+            self.input_position,
+            self.output_position,
+        ] {
+            write.write_all(&n.to_le_bytes())?;
+        }
+        let overflow = match self.cells.overflow {
+            Overflow::Wrapping => 0,
+            Overflow::Checked => 1,
+        };
+        let eof = match self.eof {
+            EofPolicy::Error => 0,
+            EofPolicy::Unchanged => 1,
+            EofPolicy::Zero => 2,
+            EofPolicy::MinusOne => 3,
+        };
+        write.write_all(&[self.cells.width.bits() as u8, overflow, eof, tag])?;
+        let cells = self.tape.cells();
+        for n in [len, self.tape.pointer(), self.tape.origin(), cells.len()] {
+            write.write_all(&(n as u64).to_le_bytes())?;
+        }
+        for cell in cells {
+            write.write_all(&cell.to_le_bytes())?;
+        }
+        write.flush()
+    }
+
+    pub fn read_from(mut read: impl Read) -> Result<Self, CheckpointError> {
+        let mut magic = [0; 4];
+        read.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(CheckpointError::NotCheckpoint);
+        }
+        let mut version = [0; 2];
+        read.read_exact(&mut version)?;
+        let version = u16::from_le_bytes(version);
+        if version != VERSION {
+            return Err(CheckpointError::UnsupportedVersion(version));
+        }
+
+        let code_hash = read_u64(&mut read)?;
+        let ip = read_usize(&mut read)?;
+        let input_position = read_u64(&mut read)?;
+        let output_position = read_u64(&mut read)?;
+
+        let mut tags = [0; 4];
+        read.read_exact(&mut tags)?;
+        let [width, overflow, eof, tag] = tags;
+        let width = match width {
+            8 => CellWidth::U8,
+            16 => CellWidth::U16,
+            32 => CellWidth::U32,
+            _ => return Err(CheckpointError::Corrupted),
+        };
+        let overflow = match overflow {
+            0 => Overflow::Wrapping,
+            1 => Overflow::Checked,
+            _ => return Err(CheckpointError::Corrupted),
+        };
+        let eof = match eof {
+            0 => EofPolicy::Error,
+            1 => EofPolicy::Unchanged,
+            2 => EofPolicy::Zero,
+            3 => EofPolicy::MinusOne,
+            _ => return Err(CheckpointError::Corrupted),
+        };
+
+        let len = read_usize(&mut read)?;
+        let config = match tag {
+            0 => TapeConfig::Fixed(len),
+            1 => TapeConfig::GrowRight(len),
+            2 => TapeConfig::Unbounded,
+            3 => TapeConfig::Circular(len),
+            _ => return Err(CheckpointError::Corrupted),
+        };
+        let pointer = read_usize(&mut read)?;
+        let origin = read_usize(&mut read)?;
+        let count = read_usize(&mut read)?;
+
+        // 壊れたファイルで大きく確保しないように，読めた分だけ伸ばす
+        let mut cells = Vec::with_capacity(count.min(1 << 16));
+        let mut buf = [0; 4];
+        for _ in 0..count {
+            read.read_exact(&mut buf)?;
+            cells.push(u32::from_le_bytes(buf));
+        }
+        let tape =
+            Tape::from_parts(config, cells, pointer, origin).ok_or(CheckpointError::Corrupted)?;
+
+        Ok(Self {
+            code_hash,
+            ip,
+            input_position,
+            output_position,
+            cells: CellConfig::new(width, overflow),
+            eof,
+            tape,
+        })
+    }
+}
+
+fn read_u64(read: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    read.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_usize(read: &mut impl Read) -> Result<usize, CheckpointError> {
+    usize::try_from(read_u64(read)?).map_err(|_| CheckpointError::Corrupted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut tape = Tape::new(TapeConfig::Unbounded);
+        tape.move_pointer(-3).unwrap();
+        *tape.at(0).unwrap() = 7;
+        let checkpoint = Checkpoint {
+            code_hash: code_hash(&OpCode::new(vec![Op::Output])),
+            ip: 5,
+            input_position: 2,
+            output_position: 9,
+            cells: CellConfig::new(CellWidth::U16, Overflow::Checked),
+            eof: EofPolicy::MinusOne,
+            tape,
+        };
+
+        let mut bytes = Vec::new();
+        checkpoint.write_to(&mut bytes).unwrap();
+        let restored = Checkpoint::read_from(bytes.as_slice()).unwrap();
+        assert_eq!(restored.code_hash, checkpoint.code_hash);
+        assert_eq!(restored.ip, 5);
+        assert_eq!((restored.input_position, restored.output_position), (2, 9));
+        assert_eq!(restored.cells, checkpoint.cells);
+        assert_eq!(restored.eof, EofPolicy::MinusOne);
+        assert_eq!(restored.tape.config(), TapeConfig::Unbounded);
+        assert_eq!(restored.tape.position(), -3);
+        assert_eq!(restored.tape.cell(-3), Some(7));
+        assert_eq!(restored.tape.cells(), checkpoint.tape.cells());
+
+        // 版が違うものと途中で切れたものは読まない
+        bytes[4] = 2;
+        assert!(matches!(
+            Checkpoint::read_from(bytes.as_slice()),
+            Err(CheckpointError::UnsupportedVersion(2))
+        ));
+        bytes[4] = 1;
+        bytes.truncate(bytes.len() - 1);
+        assert!(matches!(
+            Checkpoint::read_from(bytes.as_slice()),
+            Err(CheckpointError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn hash_depends_on_code() {
+        let a = OpCode::new(vec![Op::InclementValue(1)]);
+        let b = OpCode::new(vec![Op::InclementValue(2)]);
+        assert_eq!(code_hash(&a), code_hash(&a.clone()));
+        assert_ne!(code_hash(&a), code_hash(&b));
+
+        // 保存したファイルを他の環境でも読めるように，値は変わらない
+        let code = OpCode::new(vec![
+            Op::InclementValue(3),
+            Op::MultiplyAdd(vec![(1, 2), (-2, -1)]),
+            Op::OutputAt { offset: -1 },
+        ]);
+        assert_eq!(code_hash(&code), 0xc21a_d304_cd84_5b0d);
+    }
+}
@@ -0,0 +1,66 @@
+use std::{error::Error, fmt::Display, io};
+
+/// 実行時エラー
+#[derive(Debug)]
+pub struct RuntimeError {
+    /// 失敗した命令の位置
+    pub ip: usize,
+    /// 失敗した時のポインタの位置
+    pub pointer: usize,
+    pub kind: RuntimeErrorKind,
+}
+
+/// 実行時エラーの原因
+#[derive(Debug)]
+pub enum RuntimeErrorKind {
+    /// テープの左端を越えた
+    PointerUnderflow,
+    /// テープの右端を越えた
+    PointerOverflow,
+    /// 入力が終わっている
+    UnexpectedEof,
+    /// 折り返さないセルの値が範囲外になった
+    CellOverflow,
+    /// 伸びるテープが長さの上限を越えた
+    TapeLimitExceeded,
+    /// 入出力に失敗した
+    Io(io::Error),
+}
+
+impl From<io::Error> for RuntimeErrorKind {
+    fn from(value: io::Error) -> Self {
+        RuntimeErrorKind::Io(value)
+    }
+}
+
+impl Error for RuntimeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.kind {
+            RuntimeErrorKind::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "runtime error at instruction {} (pointer {}): {}",
+            self.ip, self.pointer, self.kind
+        )
+    }
+}
+
+impl Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeErrorKind::PointerUnderflow => write!(f, "pointer moved left of the tape"),
+            RuntimeErrorKind::PointerOverflow => write!(f, "pointer moved right of the tape"),
+            RuntimeErrorKind::UnexpectedEof => write!(f, "unexpected end of input"),
+            RuntimeErrorKind::CellOverflow => write!(f, "cell value out of range"),
+            RuntimeErrorKind::TapeLimitExceeded => write!(f, "tape grew beyond its maximum length"),
+            RuntimeErrorKind::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
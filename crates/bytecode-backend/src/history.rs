@@ -0,0 +1,121 @@
+use std::collections::VecDeque;
+
+use crate::tape::Tape;
+
+/// 逆実行のための記録の量
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Recording {
+    /// 戻れる命令の数．古い記録から捨てる
+    pub capacity: usize,
+    /// テープ全体を保存する間隔．遠くに戻る時は，保存したテープから実行し直す
+    pub snapshot_interval: usize,
+}
+
+impl Default for Recording {
+    fn default() -> Self {
+        Self {
+            capacity: 100_000,
+            snapshot_interval: 10_000,
+        }
+    }
+}
+
+/// 1命令を実行する前の状態に戻すための記録
+#[derive(Debug, Clone)]
+pub(crate) struct Undo {
+    pub ip: usize,
+    /// 実行する前のポインタの位置
+    pub position: isize,
+    /// 書き換えたセルの位置と，書き換える前の値
+    pub cells: Vec<(isize, u32)>,
+    /// 読んだ入力．戻る時に入力の先頭に戻す
+    pub input: Option<u8>,
+    /// `[>]`などが動けたセルの数．実行し直す時に同じだけ動かす
+    pub max_moves: usize,
+}
+
+/// `step`番目の命令を実行する前のテープ全体
+#[derive(Debug, Clone)]
+pub(crate) struct Snapshot {
+    pub step: u64,
+    pub ip: usize,
+    pub tape: Tape,
+    /// 保存した時に読んでいた入力のバイト数
+    pub input_position: u64,
+    /// 保存した時に出力していたバイト数
+    pub output_position: u64,
+}
+
+/// 実行した命令の記録
+#[derive(Debug, Clone)]
+pub(crate) struct History {
+    recording: Recording,
+    /// 古い順の記録．`undos[i]`は`first_step + i`番目の命令のもの
+    pub undos: VecDeque<Undo>,
+    pub first_step: u64,
+    /// 古い順の保存したテープ．どれも`first_step`以降のもの
+    pub snapshots: VecDeque<Snapshot>,
+}
+
+impl History {
+    pub fn new(recording: Recording) -> Self {
+        Self {
+            recording,
+            undos: VecDeque::new(),
+            first_step: 0,
+            snapshots: VecDeque::new(),
+        }
+    }
+
+    /// 次に実行する命令の番号
+    pub fn steps(&self) -> u64 {
+        self.first_step + self.undos.len() as u64
+    }
+
+    /// 次の命令を実行する前にテープを保存するか
+    pub fn needs_snapshot(&self) -> bool {
+        let steps = self.steps();
+        steps.is_multiple_of(self.recording.snapshot_interval.max(1) as u64)
+            && self.snapshots.back().map(|snapshot| snapshot.step) != Some(steps)
+    }
+
+    pub fn push(&mut self, undo: Undo) {
+        self.undos.push_back(undo);
+        while self.undos.len() > self.recording.capacity {
+            self.undos.pop_front();
+            self.first_step += 1;
+        }
+        // 記録がない範囲から実行し直すことはできない
+        while self
+            .snapshots
+            .front()
+            .is_some_and(|snapshot| snapshot.step < self.first_step)
+        {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// 最後の命令の記録を取り出す
+    pub fn pop(&mut self) -> Option<Undo> {
+        let undo = self.undos.pop_back()?;
+        self.truncate(self.steps());
+        Some(undo)
+    }
+
+    /// 全ての記録を捨てる．コードを入れ替えた時に使う
+    pub fn clear(&mut self) {
+        *self = Self::new(self.recording);
+    }
+
+    /// `step`番目の命令より後の記録を捨てる
+    pub fn truncate(&mut self, step: u64) {
+        self.undos.truncate((step - self.first_step) as usize);
+        while self
+            .snapshots
+            .back()
+            .is_some_and(|snapshot| snapshot.step > step)
+        {
+            self.snapshots.pop_back();
+        }
+    }
+}
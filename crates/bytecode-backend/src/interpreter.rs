@@ -1,14 +1,20 @@
-use std::io::{stdin, BufRead, BufReader, BufWriter, Read, Write};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
 
-use ast::inst::{Op, OpCode};
+use ast::cell::CellConfig;
+use ast::eof::EofPolicy;
+use ast::inst::OpCode;
 
+use crate::budget::{Budget, Outcome};
+use crate::checkpoint::{Checkpoint, CheckpointError};
+use crate::error::RuntimeError;
+use crate::history::Recording;
+use crate::machine::{Event, Machine};
+use crate::tape::{Tape, TapeConfig};
+
+/// `Machine`に`Read`と`Write`をつないだもの
 #[derive(Debug)]
 pub struct Interpreter<R: Read, W: Write> {
-    memory: Vec<u8>,
-    mem_pointer: usize,
-
-    code: OpCode,
-    ip: usize,
+    machine: Machine,
 
     read: BufReader<R>,
     write: BufWriter<W>,
@@ -17,152 +23,207 @@ pub struct Interpreter<R: Read, W: Write> {
 impl<R: Read, W: Write> Interpreter<R, W> {
     pub fn new(code: OpCode, read: R, write: W) -> Self {
         Self {
-            memory: vec![0; 30000],
-            mem_pointer: 0,
-            code,
-            ip: 0,
+            machine: Machine::new(code),
             read: BufReader::new(read),
             write: BufWriter::new(write),
         }
     }
 
-    pub fn reader(&self) -> &BufReader<R> {
-        &self.read
+    /// テープの形を変える．テープは空に戻る
+    pub fn with_tape(mut self, config: TapeConfig) -> Self {
+        self.machine = self.machine.with_tape(config);
+        self
     }
 
-    pub fn writer(&mut self) -> &BufWriter<W> {
-        &self.write
+    /// 伸びるテープの長さの上限を変える
+    pub fn with_max_tape_len(mut self, max_len: usize) -> Self {
+        self.machine = self.machine.with_max_tape_len(max_len);
+        self
     }
 
-    pub fn update(&mut self, code: OpCode) {
-        self.code = code;
-        self.ip = 0;
+    /// セルの幅と折り返しを変える．コードは同じ設定で最適化しておく
+    pub fn with_cells(mut self, cells: CellConfig) -> Self {
+        self.machine = self.machine.with_cells(cells);
+        self
     }
 
-    pub fn run(&mut self) {
-        while self.check_token_pointer() {
-            self.step();
-        }
+    /// 入力が終わった後の`,`の扱いを変える
+    pub fn with_eof(mut self, eof: EofPolicy) -> Self {
+        self.machine = self.machine.with_eof(eof);
+        self
+    }
 
-        self.write.flush().unwrap();
+    /// `run`で実行できる量を決める
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.machine = self.machine.with_budget(budget);
+        self
     }
 
-    pub fn step(&mut self) {
-        if !self.check_token_pointer() {
-            return;
-        }
+    /// 実行した命令を記録して，`step_back`と`run_back_to`で戻れるようにする
+    pub fn with_recording(mut self, recording: Recording) -> Self {
+        self.machine = self.machine.with_recording(recording);
+        self
+    }
 
-        match *self.advance() {
-            Op::InclementPointer(count) => self.inclement_pointer(count),
-            Op::DecrementPointer(count) => self.decrement_pointer(count),
-            Op::InclementValue(count) => self.inclement_value(count),
-            Op::DecrementValue(count) => self.decrement_value(count),
-            Op::Output => self.output(),
-            Op::Input => self.input(),
-            Op::LoopStart { if_zero_add } => self.loop_start(if_zero_add),
-            Op::LoopEnd { if_non_zero_sub } => self.loop_end(if_non_zero_sub),
-            Op::Load(n) => self.load(n),
-            Op::SumRight(count) => self.sum_right(count),
-            Op::SumLeft(count) => self.sum_left(count),
-            Op::JumpZeroRight { per } => self.jump_zero_right(per),
-            Op::JumpZeroLeft { per } => self.jump_zero_left(per),
-        }
+    pub fn machine(&self) -> &Machine {
+        &self.machine
     }
 
-    fn check_token_pointer(&self) -> bool {
-        self.ip < self.code.vec().len()
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
     }
 
-    fn advance(&mut self) -> &Op {
-        let op = &self.code.vec()[self.ip];
-        self.ip += 1;
-        op
+    /// 実行できる量の残り
+    pub fn budget(&self) -> &Budget {
+        self.machine.budget()
     }
 
-    fn inclement_pointer(&mut self, count: usize) {
-        self.mem_pointer += count;
+    /// 制限に達した後に増やして，`run`で続きを実行する
+    pub fn budget_mut(&mut self) -> &mut Budget {
+        self.machine.budget_mut()
     }
 
-    fn decrement_pointer(&mut self, count: usize) {
-        self.mem_pointer -= count;
+    pub fn reader(&self) -> &BufReader<R> {
+        &self.read
     }
 
-    fn inclement_value(&mut self, count: usize) {
-        self.memory[self.mem_pointer] =
-            self.memory[self.mem_pointer].wrapping_add((count % u8::MAX as usize) as u8);
+    pub fn writer(&mut self) -> &BufWriter<W> {
+        &self.write
     }
 
-    fn decrement_value(&mut self, count: usize) {
-        self.memory[self.mem_pointer] =
-            self.memory[self.mem_pointer].wrapping_sub((count % u8::MAX as usize) as u8);
+    pub fn tape(&self) -> &Tape {
+        self.machine.tape()
     }
 
-    fn output(&mut self) {
-        self.write
-            .write_all(&[self.memory[self.mem_pointer]])
-            .unwrap();
+    /// デバッガなどからセルを書き換える
+    pub fn tape_mut(&mut self) -> &mut Tape {
+        self.machine.tape_mut()
+    }
 
-        self.write.flush().unwrap();
+    /// テープ
+    pub fn memory(&self) -> &[u32] {
+        self.machine.memory()
     }
 
-    fn input(&mut self) {
-        self.read
-            .read_exact(std::slice::from_mut(&mut self.memory[self.mem_pointer]))
-            // .read_line(&mut self.memory[self.mem_pointer..self.mem_pointer + 1])
-            .unwrap();
+    /// `memory()`での現在のセルの位置
+    pub fn pointer(&self) -> usize {
+        self.machine.pointer()
+    }
 
-        
-        dbg!(self.memory[self.mem_pointer] as char);
+    /// 全ての命令を実行し終えたか
+    pub fn is_finished(&self) -> bool {
+        self.machine.is_finished()
     }
 
-    fn loop_start(&mut self, if_zero_add: usize) {
-        if self.memory[self.mem_pointer] != 0 {
-            return;
-        }
+    pub fn update(&mut self, code: OpCode) {
+        self.machine.update(code);
+    }
 
-        self.ip += if_zero_add;
+    /// 最後まで，または`budget`の制限に達するまで実行する
+    pub fn run(&mut self) -> Result<Outcome, RuntimeError> {
+        let result = self.run_machine();
+        self.write
+            .flush()
+            .map_err(|e| self.machine.error(e.into()))?;
+        result
     }
 
-    fn loop_end(&mut self, if_non_zero_sub: usize) {
-        if self.memory[self.mem_pointer] == 0 {
-            return;
+    fn run_machine(&mut self) -> Result<Outcome, RuntimeError> {
+        loop {
+            match self.machine.resume()? {
+                Event::NeedInput => self.read_input()?,
+                Event::Output(byte) => {
+                    if let Err(e) = self.write_output(byte) {
+                        // 書き込めなかった出力は使った量に数えない
+                        let budget = self.machine.budget_mut();
+                        for n in [&mut budget.steps, &mut budget.output]
+                            .into_iter()
+                            .flatten()
+                        {
+                            *n += 1;
+                        }
+                        return Err(e);
+                    }
+                }
+                Event::Halted => return Ok(Outcome::Finished),
+                Event::FuelExhausted(limit) => return Ok(Outcome::LimitReached(limit)),
+            }
         }
+    }
 
-        self.ip -= if_non_zero_sub;
+    /// 命令を1つ実行する．失敗した場合，命令の位置は失敗した命令のまま進まない
+    pub fn step(&mut self) -> Result<(), RuntimeError> {
+        loop {
+            match self.machine.step()? {
+                Some(Event::NeedInput) => self.read_input()?,
+                Some(Event::Output(byte)) => return self.write_output(byte),
+                _ => return Ok(()),
+            }
+        }
     }
 
-    fn load(&mut self, n: u8) {
-        self.memory[self.mem_pointer] = n;
+    /// 直前に実行した命令を，実行する前に戻す．読んだ入力は次に読む入力に戻る
+    pub fn step_back(&mut self) -> bool {
+        self.machine.step_back()
     }
 
-    fn sum_right(&mut self, count: usize) {
-        let value = self.memory[self.mem_pointer];
-        let target_index = self.mem_pointer + count;
-        self.memory[target_index] = self.memory[target_index].wrapping_add(value);
+    /// 記録の中で最後に`breakpoint`の位置の命令を実行する前まで戻る
+    pub fn run_back_to(&mut self, breakpoint: impl FnMut(usize) -> bool) -> bool {
+        self.machine.run_back_to(breakpoint)
     }
 
-    fn sum_left(&mut self, count: usize) {
-        let value = self.memory[self.mem_pointer];
-        let target_index = self.mem_pointer - count;
-        self.memory[target_index] = self.memory[target_index].wrapping_add(value);
+    /// 今の状態を保存する．書き込めていない出力は先に書き出す
+    pub fn checkpoint(&mut self) -> Result<Checkpoint, RuntimeError> {
+        self.write
+            .flush()
+            .map_err(|e| self.machine.error(e.into()))?;
+        Ok(self.machine.checkpoint())
     }
 
-    fn jump_zero_right(&mut self, per: usize) {
-        while self.memory[self.mem_pointer] != 0 {
-            self.mem_pointer += per;
+    /// 保存した状態に戻す．入力は保存した時と同じものを始めから渡し，読んだ分を読み飛ばす
+    pub fn restore(&mut self, checkpoint: Checkpoint) -> Result<(), CheckpointError> {
+        let position = checkpoint.input_position;
+        self.machine.restore(checkpoint)?;
+        let skipped = io::copy(&mut (&mut self.read).take(position), &mut io::sink())?;
+        if skipped < position {
+            return Err(CheckpointError::Io(ErrorKind::UnexpectedEof.into()));
         }
+        Ok(())
     }
 
-    fn jump_zero_left(&mut self, per: usize) {
-        while self.memory[self.mem_pointer] != 0 {
-            self.mem_pointer -= per;
+    /// 1バイト読んで`machine`に渡す．入力が終わっていれば`machine`にそう伝える
+    fn read_input(&mut self) -> Result<(), RuntimeError> {
+        let mut buf = [0];
+        match self.read.read_exact(&mut buf) {
+            Ok(()) => self.machine.provide_input(&buf),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => self.machine.close_input(),
+            Err(e) => return Err(self.machine.error(e.into())),
         }
+        Ok(())
+    }
+
+    /// 書き込みに失敗した場合，出力の命令を実行する前に戻す
+    fn write_output(&mut self, byte: u8) -> Result<(), RuntimeError> {
+        let result = self
+            .write
+            .write_all(&[byte])
+            .and_then(|_| self.write.flush());
+        result.map_err(|e| {
+            self.machine.rewind_output();
+            self.machine.error(e.into())
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use super::*;
+    use crate::budget::Limit;
+    use crate::error::RuntimeErrorKind;
+    use ast::cell::{CellWidth, Overflow};
+    use ast::inst::Op;
 
     #[derive(Clone, Default, Debug)]
     struct MyReader {
@@ -207,7 +268,7 @@ mod tests {
             MyReader::default(),
             MyWriter::default(),
         );
-        interpreter.run();
+        interpreter.run().unwrap();
 
         assert_eq!(
             interpreter.write.into_inner().unwrap().output,
@@ -232,7 +293,7 @@ mod tests {
         ]);
 
         let mut interpreter = Interpreter::new(code, MyReader::default(), MyWriter::default());
-        interpreter.run();
+        interpreter.run().unwrap();
 
         assert_eq!(
             interpreter.write.into_inner().unwrap().output,
@@ -270,11 +331,372 @@ mod tests {
             },
             MyWriter::default(),
         );
-        interpreter.run();
+        interpreter.run().unwrap();
 
         assert_eq!(
             interpreter.write.into_inner().unwrap().output,
             input_string.as_bytes()
         );
     }
+
+    #[test]
+    fn multiply_add() {
+        // +++>+<[>+++>--<<-].>.>.
+        let code = OpCode::new(vec![
+            Op::InclementValue(3),
+            Op::InclementPointer(1),
+            Op::InclementValue(1),
+            Op::DecrementPointer(1),
+            Op::MultiplyAdd(vec![(1, 3), (2, -2)]),
+            Op::Output,
+            Op::InclementPointer(1),
+            Op::Output,
+            Op::InclementPointer(1),
+            Op::Output,
+        ]);
+
+        let mut interpreter = Interpreter::new(code, MyReader::default(), MyWriter::default());
+        interpreter.run().unwrap();
+
+        assert_eq!(
+            interpreter.write.into_inner().unwrap().output,
+            vec![0, 10, 250]
+        );
+    }
+
+    #[test]
+    fn offset_addressed() {
+        let code = OpCode::new(vec![
+            Op::LoadAt { offset: 2, n: 5 },
+            Op::InclementValueAt {
+                offset: 1,
+                count: 3,
+            },
+            Op::DecrementValueAt {
+                offset: 2,
+                count: 3,
+            },
+            Op::InclementPointer(2),
+            Op::OutputAt { offset: 0 },
+            Op::OutputAt { offset: -1 },
+            Op::InputAt { offset: -2 },
+            Op::OutputAt { offset: -2 },
+        ]);
+
+        let mut interpreter = Interpreter::new(
+            code,
+            MyReader {
+                input: b"x".to_vec(),
+            },
+            MyWriter::default(),
+        );
+        interpreter.run().unwrap();
+
+        assert_eq!(
+            interpreter.write.into_inner().unwrap().output,
+            vec![2, 3, b'x']
+        );
+    }
+
+    #[test]
+    fn wraps_modulo_256() {
+        let code = OpCode::new(vec![
+            Op::InclementValue(257),
+            Op::Output,
+            Op::DecrementValue(258),
+            Op::Output,
+        ]);
+
+        let mut interpreter = Interpreter::new(code, MyReader::default(), MyWriter::default());
+        interpreter.run().unwrap();
+
+        assert_eq!(interpreter.write.into_inner().unwrap().output, vec![1, 255]);
+    }
+
+    #[test]
+    fn reports_runtime_errors() {
+        let run = |code: Vec<Op>, input: &[u8]| {
+            let mut interpreter = Interpreter::new(OpCode::new(code), input, Vec::new());
+            interpreter.run().unwrap_err()
+        };
+
+        let error = run(vec![Op::InclementPointer(2), Op::DecrementPointer(3)], b"");
+        assert_eq!((error.ip, error.pointer), (1, 2));
+        assert!(matches!(error.kind, RuntimeErrorKind::PointerUnderflow));
+
+        let error = run(
+            vec![Op::InclementValueAt {
+                offset: 30000,
+                count: 1,
+            }],
+            b"",
+        );
+        assert!(matches!(error.kind, RuntimeErrorKind::PointerOverflow));
+
+        let error = run(vec![Op::Input, Op::Input], b"a");
+        assert_eq!(error.ip, 1);
+        assert!(matches!(error.kind, RuntimeErrorKind::UnexpectedEof));
+
+        let error = run(
+            vec![Op::InclementValue(1), Op::JumpZeroLeft { per: 1 }],
+            b"",
+        );
+        assert!(matches!(error.kind, RuntimeErrorKind::PointerUnderflow));
+    }
+
+    #[test]
+    fn reports_io_errors() {
+        struct Broken;
+
+        impl Write for Broken {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("broken"))
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut interpreter = Interpreter::new(
+            OpCode::new(vec![Op::InclementValue(1), Op::Output]),
+            MyReader::default(),
+            Broken,
+        );
+        let error = interpreter
+            .step()
+            .and_then(|_| interpreter.step())
+            .unwrap_err();
+
+        assert_eq!(error.ip, 1);
+        assert!(matches!(error.kind, RuntimeErrorKind::Io(_)));
+        assert!(!interpreter.is_finished());
+    }
+
+    #[test]
+    fn tape_configs() {
+        let code = || OpCode::new(vec![Op::DecrementPointer(1), Op::InclementValue(3)]);
+
+        let mut interpreter = Interpreter::new(code(), MyReader::default(), MyWriter::default());
+        assert!(interpreter.run().is_err());
+
+        let mut interpreter = Interpreter::new(code(), MyReader::default(), MyWriter::default())
+            .with_tape(TapeConfig::Unbounded);
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.tape().position(), -1);
+        assert_eq!(interpreter.memory()[interpreter.pointer()], 3);
+
+        let mut interpreter = Interpreter::new(code(), MyReader::default(), MyWriter::default())
+            .with_tape(TapeConfig::Circular(8));
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.pointer(), 7);
+        assert_eq!(interpreter.memory()[7], 3);
+
+        // +[>+] は伸びるテープの上限で止まる
+        let code = OpCode::new(vec![
+            Op::InclementValue(1),
+            Op::LoopStart { if_zero_add: 3 },
+            Op::InclementPointer(1),
+            Op::InclementValue(1),
+            Op::LoopEnd { if_non_zero_sub: 3 },
+        ]);
+        for tape in [TapeConfig::GrowRight(1), TapeConfig::Unbounded] {
+            let mut interpreter =
+                Interpreter::new(code.clone(), MyReader::default(), MyWriter::default())
+                    .with_max_tape_len(1000)
+                    .with_tape(tape);
+            let error = interpreter.run().unwrap_err();
+            assert!(matches!(error.kind, RuntimeErrorKind::TapeLimitExceeded));
+            assert_eq!(error.ip, 2);
+            assert_eq!(interpreter.memory().len(), 1000);
+        }
+    }
+
+    #[test]
+    fn eof_policies() {
+        let run = |eof| {
+            let code = OpCode::new(vec![Op::InclementValue(7), Op::Input, Op::Input]);
+            let mut interpreter =
+                Interpreter::new(code, b"a".as_slice(), MyWriter::default()).with_eof(eof);
+            interpreter.run().map(|_| interpreter.memory()[0])
+        };
+
+        assert!(run(EofPolicy::Error).is_err());
+        assert_eq!(run(EofPolicy::Unchanged).unwrap(), b'a' as u32);
+        assert_eq!(run(EofPolicy::Zero).unwrap(), 0);
+        assert_eq!(run(EofPolicy::MinusOne).unwrap(), 255);
+    }
+
+    #[test]
+    fn cell_widths() {
+        // 300を足して出力し，1減らしたセルを右に2倍して足す
+        let code = || {
+            OpCode::new(vec![
+                Op::InclementValue(300),
+                Op::Output,
+                Op::MultiplyAdd(vec![(1, 2)]),
+                Op::DecrementValue(1),
+            ])
+        };
+        let run = |cells| {
+            let mut interpreter =
+                Interpreter::new(code(), MyReader::default(), MyWriter::default())
+                    .with_cells(cells);
+            let result = interpreter.run();
+            let memory = interpreter.memory()[..2].to_vec();
+            let output = interpreter.write.into_inner().unwrap().output;
+            result.map(|_| (memory, output))
+        };
+
+        assert_eq!(
+            run(CellConfig::default()).unwrap(),
+            (vec![255, 88], vec![44])
+        );
+        assert_eq!(
+            run(CellConfig::new(CellWidth::U16, Overflow::Wrapping)).unwrap(),
+            (vec![65535, 600], vec![44])
+        );
+
+        let error = run(CellConfig::new(CellWidth::U16, Overflow::Checked)).unwrap_err();
+        assert_eq!(error.ip, 3);
+        assert!(matches!(error.kind, RuntimeErrorKind::CellOverflow));
+        let error = run(CellConfig::new(CellWidth::U8, Overflow::Checked)).unwrap_err();
+        assert_eq!(error.ip, 0);
+    }
+
+    #[test]
+    fn resumes_after_budget() {
+        // +[.] を2回ずつ出力して止める
+        let code = OpCode::new(vec![
+            Op::InclementValue(1),
+            Op::LoopStart { if_zero_add: 2 },
+            Op::Output,
+            Op::LoopEnd { if_non_zero_sub: 2 },
+        ]);
+        let mut interpreter = Interpreter::new(code, MyReader::default(), MyWriter::default())
+            .with_budget(Budget::unlimited().with_output(2));
+
+        assert_eq!(
+            interpreter.run().unwrap(),
+            Outcome::LimitReached(Limit::Output)
+        );
+        assert_eq!(interpreter.writer().get_ref().output, vec![1, 1]);
+
+        interpreter.budget_mut().output = Some(1);
+        interpreter.budget_mut().steps = Some(2);
+        assert_eq!(
+            interpreter.run().unwrap(),
+            Outcome::LimitReached(Limit::Steps)
+        );
+        assert_eq!(interpreter.writer().get_ref().output, vec![1, 1, 1]);
+        assert_eq!(interpreter.budget().output, Some(0));
+
+        *interpreter.budget_mut() = Budget::unlimited().with_time(Duration::from_millis(10));
+        assert_eq!(
+            interpreter.run().unwrap(),
+            Outcome::LimitReached(Limit::Time)
+        );
+        assert_eq!(interpreter.budget().time, Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn steps_back_over_input() {
+        // ,>,<.
+        let code = OpCode::new(vec![
+            Op::Input,
+            Op::InclementPointer(1),
+            Op::Input,
+            Op::DecrementPointer(1),
+            Op::Output,
+        ]);
+        let mut interpreter = Interpreter::new(
+            code,
+            MyReader {
+                input: b"ab".to_vec(),
+            },
+            MyWriter::default(),
+        )
+        .with_recording(Recording::default());
+
+        for _ in 0..4 {
+            interpreter.step().unwrap();
+        }
+        assert_eq!(&interpreter.memory()[..2], &[97, 98]);
+
+        assert!(interpreter.run_back_to(|ip| ip == 2));
+        assert_eq!(interpreter.machine().ip(), 2);
+        assert_eq!(interpreter.pointer(), 1);
+        assert_eq!(&interpreter.memory()[..2], &[97, 0]);
+
+        // 戻した入力をもう一度読む
+        interpreter.run().unwrap();
+        assert_eq!(&interpreter.memory()[..2], &[97, 98]);
+        assert_eq!(interpreter.writer().get_ref().output, b"a");
+
+        while interpreter.step_back() {}
+        assert_eq!(interpreter.machine().ip(), 0);
+        assert_eq!(&interpreter.memory()[..2], &[0, 0]);
+        assert!(!interpreter.run_back_to(|_| true));
+    }
+
+    #[test]
+    fn resumes_from_checkpoint() {
+        // ,[.,] で入力をそのまま出力する
+        let code = OpCode::new(vec![
+            Op::Input,
+            Op::LoopStart { if_zero_add: 3 },
+            Op::Output,
+            Op::Input,
+            Op::LoopEnd { if_non_zero_sub: 3 },
+        ]);
+        // `MyReader`は同じ入力を繰り返すので，読んだ分だけ進むスライスを使う
+        let reader = || &b"hello"[..];
+        let mut interpreter = Interpreter::new(code.clone(), reader(), MyWriter::default())
+            .with_eof(EofPolicy::Zero)
+            .with_budget(Budget::unlimited().with_steps(8));
+        interpreter.run().unwrap();
+        let checkpoint = interpreter.checkpoint().unwrap();
+        assert_eq!(interpreter.writer().get_ref().output, b"he");
+        assert_eq!(
+            (checkpoint.input_position, checkpoint.output_position),
+            (3, 2)
+        );
+
+        let mut bytes = Vec::new();
+        checkpoint.write_to(&mut bytes).unwrap();
+        let checkpoint = Checkpoint::read_from(bytes.as_slice()).unwrap();
+
+        // 同じ入力を始めから渡すと，読んだ分を読み飛ばして続ける
+        let mut resumed =
+            Interpreter::new(code.clone(), reader(), MyWriter::default()).with_eof(EofPolicy::Zero);
+        resumed.restore(checkpoint.clone()).unwrap();
+        assert_eq!(resumed.run().unwrap(), Outcome::Finished);
+        assert_eq!(resumed.writer().get_ref().output, b"llo");
+        assert_eq!(resumed.machine().output_position(), 5);
+
+        let mut other =
+            Interpreter::new(OpCode::new(vec![Op::Output]), reader(), MyWriter::default());
+        assert!(matches!(
+            other.restore(checkpoint.clone()),
+            Err(CheckpointError::CodeMismatch)
+        ));
+
+        // テープの形，セルの幅と折り返しか`EofPolicy`が違えば続けない
+        let same_code = || {
+            Interpreter::new(code.clone(), reader(), MyWriter::default()).with_eof(EofPolicy::Zero)
+        };
+        let cells = CellConfig::new(CellWidth::U32, Overflow::Wrapping);
+        let mismatched = [
+            (same_code().with_tape(TapeConfig::Unbounded), "tape"),
+            (same_code().with_cells(cells), "cell width or overflow"),
+            (same_code().with_eof(EofPolicy::Unchanged), "EOF policy"),
+        ];
+        for (mut other, setting) in mismatched {
+            assert!(matches!(
+                other.restore(checkpoint.clone()),
+                Err(CheckpointError::SettingsMismatch(s)) if s == setting
+            ));
+            assert_eq!(other.machine().ip(), 0);
+        }
+    }
 }
@@ -1 +1,7 @@
+pub mod budget;
+pub mod checkpoint;
+pub mod error;
+pub mod history;
 pub mod interpreter;
+pub mod machine;
+pub mod tape;
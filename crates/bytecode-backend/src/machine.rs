@@ -0,0 +1,1236 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use ast::cell::CellConfig;
+use ast::eof::EofPolicy;
+use ast::inst::{Op, OpCode};
+
+use crate::budget::{Budget, Limit};
+use crate::checkpoint::{code_hash, Checkpoint, CheckpointError};
+use crate::error::{RuntimeError, RuntimeErrorKind};
+use crate::history::{History, Recording, Snapshot, Undo};
+use crate::tape::{Addressing, Circular, Linear, Tape, TapeConfig};
+
+/// `step`で`[>]`などが1度に動くセルの数の上限．0のセルが見つからなければ同じ命令から続ける
+const SCAN_CHUNK: usize = 1024;
+
+/// `Machine::resume`が止まった理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// 入力が必要．`provide_input`か`close_input`の後に`resume`を呼ぶと，同じ命令から続ける
+    NeedInput,
+    /// 1バイト出力した
+    Output(u8),
+    /// 全ての命令を実行し終えた
+    Halted,
+    /// `Budget`の制限に達したので，次の命令を実行する前に止まった
+    FuelExhausted(Limit),
+}
+
+/// 入出力を持たない実行器
+///
+/// `resume`は入力が必要になるか，出力するか，終わるか，制限に達するまで実行して戻る．
+/// イベントループなどから少しずつ動かせる
+#[derive(Debug)]
+pub struct Machine {
+    tape: Tape,
+    cells: CellConfig,
+    eof: EofPolicy,
+    budget: Budget,
+
+    code: OpCode,
+    ip: usize,
+
+    /// まだ読まれていない入力
+    input: VecDeque<u8>,
+    /// `input`の後に入力が続かないか
+    input_closed: bool,
+    /// 読んだ入力のバイト数
+    input_position: u64,
+    /// 出力したバイト数
+    output_position: u64,
+
+    /// `with_recording`で記録を取る時の，戻るための記録
+    history: Option<History>,
+}
+
+impl Machine {
+    pub fn new(code: OpCode) -> Self {
+        Self {
+            tape: Tape::new(TapeConfig::default()),
+            cells: CellConfig::default(),
+            eof: EofPolicy::default(),
+            budget: Budget::unlimited(),
+            code,
+            ip: 0,
+            input: VecDeque::new(),
+            input_closed: false,
+            input_position: 0,
+            output_position: 0,
+            history: None,
+        }
+    }
+
+    /// テープの形を変える．テープは空に戻る
+    pub fn with_tape(mut self, config: TapeConfig) -> Self {
+        self.tape = Tape::new(config).with_max_len(self.tape.max_len());
+        self
+    }
+
+    /// 伸びるテープの長さの上限を変える
+    pub fn with_max_tape_len(mut self, max_len: usize) -> Self {
+        self.tape = self.tape.with_max_len(max_len);
+        self
+    }
+
+    /// セルの幅と折り返しを変える．コードは同じ設定で最適化しておく
+    pub fn with_cells(mut self, cells: CellConfig) -> Self {
+        self.cells = cells;
+        self
+    }
+
+    /// 入力が終わった後の`,`の扱いを変える
+    pub fn with_eof(mut self, eof: EofPolicy) -> Self {
+        self.eof = eof;
+        self
+    }
+
+    /// `resume`で実行できる量を決める
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// 実行した命令を記録して，`step_back`と`run_back_to`で戻れるようにする
+    ///
+    /// 記録は`recording.capacity`命令分までで，古いものから捨てる
+    pub fn with_recording(mut self, recording: Recording) -> Self {
+        self.history = Some(History::new(recording));
+        self
+    }
+
+    /// 戻れる命令の数
+    pub fn recorded_steps(&self) -> usize {
+        self.history
+            .as_ref()
+            .map_or(0, |history| history.undos.len())
+    }
+
+    /// 実行できる量の残り
+    pub fn budget(&self) -> &Budget {
+        &self.budget
+    }
+
+    /// 制限に達した後に増やして，`resume`で続きを実行する
+    pub fn budget_mut(&mut self) -> &mut Budget {
+        &mut self.budget
+    }
+
+    pub fn tape(&self) -> &Tape {
+        &self.tape
+    }
+
+    /// デバッガなどからセルを書き換える．書き換えは記録に残らない
+    pub fn tape_mut(&mut self) -> &mut Tape {
+        &mut self.tape
+    }
+
+    pub fn code(&self) -> &OpCode {
+        &self.code
+    }
+
+    /// テープ
+    pub fn memory(&self) -> &[u32] {
+        self.tape.cells()
+    }
+
+    /// `memory()`での現在のセルの位置
+    pub fn pointer(&self) -> usize {
+        self.tape.pointer()
+    }
+
+    /// 次に実行する命令の位置
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    /// 読んだ入力のバイト数．戻った分は数えない
+    pub fn input_position(&self) -> u64 {
+        self.input_position
+    }
+
+    /// 出力したバイト数．戻った分は数えない
+    pub fn output_position(&self) -> u64 {
+        self.output_position
+    }
+
+    /// 全ての命令を実行し終えたか
+    pub fn is_finished(&self) -> bool {
+        !self.check_token_pointer()
+    }
+
+    pub fn update(&mut self, code: OpCode) {
+        self.code = code;
+        self.ip = 0;
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+    }
+
+    /// 入力を後ろに加える
+    pub fn provide_input(&mut self, bytes: &[u8]) {
+        self.input.extend(bytes);
+    }
+
+    /// これ以上入力がないことを伝える．残りの入力を読み終えた後の`,`は`EofPolicy`に従う
+    pub fn close_input(&mut self) {
+        self.input_closed = true;
+    }
+
+    /// 今の状態を保存する
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            code_hash: code_hash(&self.code),
+            ip: self.ip,
+            input_position: self.input_position,
+            output_position: self.output_position,
+            cells: self.cells,
+            eof: self.eof,
+            tape: self.tape.clone(),
+        }
+    }
+
+    /// 保存した状態に戻す．入力は`input_position`バイト目から`provide_input`し直す
+    ///
+    /// 保存した時とコード，テープの形，セルの幅と折り返しか`EofPolicy`が違えば何もしない．記録は捨てる
+    pub fn restore(&mut self, checkpoint: Checkpoint) -> Result<(), CheckpointError> {
+        if checkpoint.code_hash != code_hash(&self.code) {
+            return Err(CheckpointError::CodeMismatch);
+        }
+        if checkpoint.tape.config() != self.tape.config() {
+            return Err(CheckpointError::SettingsMismatch("tape"));
+        }
+        if checkpoint.cells != self.cells {
+            return Err(CheckpointError::SettingsMismatch("cell width or overflow"));
+        }
+        if checkpoint.eof != self.eof {
+            return Err(CheckpointError::SettingsMismatch("EOF policy"));
+        }
+        if checkpoint.ip > self.code.vec().len() {
+            return Err(CheckpointError::Corrupted);
+        }
+
+        self.tape = checkpoint.tape.with_max_len(self.tape.max_len());
+        self.ip = checkpoint.ip;
+        self.input_position = checkpoint.input_position;
+        self.output_position = checkpoint.output_position;
+        self.input.clear();
+        self.input_closed = false;
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+        Ok(())
+    }
+
+    /// 次のイベントまで実行する
+    pub fn resume(&mut self) -> Result<Event, RuntimeError> {
+        let start = Instant::now();
+        let result = self.resume_within_budget(start);
+        if let Some(time) = &mut self.budget.time {
+            *time = time.saturating_sub(start.elapsed());
+        }
+        result
+    }
+
+    fn resume_within_budget(&mut self, start: Instant) -> Result<Event, RuntimeError> {
+        // 時刻を調べるのは，この命令数ごとにする
+        const CLOCK_INTERVAL: usize = 1 << 16;
+
+        let deadline = self.budget.time.and_then(|time| start.checked_add(time));
+        let recording = self.history.is_some();
+        // 次に時刻を調べるまでに実行できる命令の数
+        let mut until_clock: usize = 0;
+        loop {
+            if !self.check_token_pointer() {
+                return Ok(Event::Halted);
+            }
+            if self.budget.steps == Some(0) {
+                return Ok(Event::FuelExhausted(Limit::Steps));
+            }
+            if until_clock == 0 {
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    return Ok(Event::FuelExhausted(Limit::Time));
+                }
+                until_clock = match deadline {
+                    Some(_) => CLOCK_INTERVAL,
+                    None => usize::MAX,
+                };
+            }
+
+            // `[>]`などは動いたセルの数だけ命令を実行したと数える
+            let max_moves = self.budget.steps.unwrap_or(usize::MAX).min(until_clock);
+
+            // 記録しなければ，入出力のない命令は内側のループでまとめて実行し，制限はまとめて数える
+            let executed = if recording {
+                0
+            } else {
+                self.run_plain(max_moves)
+            };
+            if executed > 0 {
+                if let Some(steps) = &mut self.budget.steps {
+                    *steps -= executed;
+                }
+                until_clock -= executed;
+                continue;
+            }
+
+            let is_output = matches!(self.code.vec()[self.ip], Op::Output | Op::OutputAt { .. });
+            if is_output && self.budget.output == Some(0) {
+                return Ok(Event::FuelExhausted(Limit::Output));
+            }
+
+            let (event, executed) = if recording {
+                self.step_recording(max_moves)?
+            } else {
+                self.step_plain(max_moves)?
+            };
+            if event == Some(Event::NeedInput) {
+                return Ok(Event::NeedInput);
+            }
+
+            if let Some(steps) = &mut self.budget.steps {
+                *steps -= executed;
+            }
+            if let (true, Some(output)) = (is_output, &mut self.budget.output) {
+                *output -= 1;
+            }
+            until_clock -= executed;
+
+            if let Some(event) = event {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// 入出力のない命令を，テープの形に合わせた内側のループでまとめて実行する
+    ///
+    /// `limit`命令まで実行し，入出力の命令，テープを伸ばすか範囲外に出る命令と，
+    /// 折り返さない場合に範囲外へ増減する命令の前で止まる．止まった命令は`step_plain`で実行する．
+    /// 実行した命令の数として数える量を返す
+    fn run_plain(&mut self, limit: usize) -> usize {
+        match self.tape.config() {
+            TapeConfig::Circular(_) => self.run_plain_with::<Circular>(limit),
+            _ => self.run_plain_with::<Linear>(limit),
+        }
+    }
+
+    fn run_plain_with<A: Addressing>(&mut self, limit: usize) -> usize {
+        let code = self.code.vec();
+        let config = self.cells;
+        let (cells, pointer) = self.tape.parts_mut();
+        let len = cells.len();
+        let mut p = *pointer;
+        let mut ip = self.ip;
+        let mut executed = 0;
+        let arith = Arith::new(&config);
+
+        macro_rules! index {
+            ($offset:expr) => {
+                match A::index(len, p, $offset) {
+                    Some(index) => index,
+                    None => break,
+                }
+            };
+        }
+        macro_rules! add {
+            ($index:expr, $method:ident, $count:expr) => {{
+                let index = $index;
+                match arith.$method(cells[index], $count) {
+                    Some(value) => cells[index] = value,
+                    None => break,
+                }
+            }};
+        }
+
+        while executed < limit {
+            let Some(op) = code.get(ip) else {
+                break;
+            };
+            match *op {
+                Op::InclementPointer(count) => p = index!(count as isize),
+                Op::DecrementPointer(count) => p = index!(-(count as isize)),
+                Op::InclementValue(count) => add!(p, add, count),
+                Op::DecrementValue(count) => add!(p, sub, count),
+                Op::InclementValueAt { offset, count } => add!(index!(offset), add, count),
+                Op::DecrementValueAt { offset, count } => add!(index!(offset), sub, count),
+                Op::LoopStart { if_zero_add } => {
+                    if cells[p] == 0 {
+                        ip += if_zero_add;
+                    }
+                }
+                Op::LoopEnd { if_non_zero_sub } => {
+                    if cells[p] != 0 {
+                        ip -= if_non_zero_sub;
+                    }
+                }
+                Op::Load(n) => cells[p] = n,
+                Op::LoadAt { offset, n } => cells[index!(offset)] = n,
+                Op::SumRight(count) => {
+                    if !multiply_add_in::<A>(cells, p, &config, &[(count as isize, 1)]) {
+                        break;
+                    }
+                }
+                Op::SumLeft(count) => {
+                    if !multiply_add_in::<A>(cells, p, &config, &[(-(count as isize), 1)]) {
+                        break;
+                    }
+                }
+                Op::MultiplyAdd(ref targets) => {
+                    if !multiply_add_in::<A>(cells, p, &config, targets) {
+                        break;
+                    }
+                }
+                Op::JumpZeroRight { per } | Op::JumpZeroLeft { per } => {
+                    let per = match *op {
+                        Op::JumpZeroRight { .. } => per as isize,
+                        _ => -(per as isize),
+                    };
+                    // 動いたセルの数だけ実行したと数え，`limit`に達したら同じ命令から続ける
+                    let mut moves = 0;
+                    while cells[p] != 0 && executed + moves < limit {
+                        match A::index(len, p, per) {
+                            Some(index) => p = index,
+                            None => break,
+                        }
+                        moves += 1;
+                    }
+                    if cells[p] != 0 {
+                        executed += moves;
+                        break;
+                    }
+                    executed += moves.max(1);
+                    ip += 1;
+                    continue;
+                }
+                Op::Output | Op::OutputAt { .. } | Op::Input | Op::InputAt { .. } => break,
+            }
+            executed += 1;
+            ip += 1;
+        }
+
+        *pointer = p;
+        self.ip = ip;
+        executed
+    }
+
+    /// `Budget`に関係なく命令を1つ実行し，起きたイベントを返す
+    ///
+    /// 入力が必要な場合と失敗した場合は，命令の位置は進まない．
+    /// `[>]`などが`SCAN_CHUNK`セル動いても0のセルが見つからない場合も，命令の位置は進まない
+    pub fn step(&mut self) -> Result<Option<Event>, RuntimeError> {
+        let (event, _) = match self.history {
+            Some(_) => self.step_recording(SCAN_CHUNK)?,
+            None => self.step_plain(SCAN_CHUNK)?,
+        };
+        Ok(event)
+    }
+
+    /// 記録を取らずに命令を1つ実行する．`[>]`などは`max_moves`セルまで動く
+    ///
+    /// 起きたイベントと，実行した命令の数として数える量を返す
+    fn step_plain(&mut self, max_moves: usize) -> Result<(Option<Event>, usize), RuntimeError> {
+        if !self.check_token_pointer() {
+            return Ok((Some(Event::Halted), 0));
+        }
+        if self.needs_input() {
+            return Ok((Some(Event::NeedInput), 0));
+        }
+
+        let ip = self.ip;
+        self.ip += 1;
+
+        let mut executed = 1;
+        let event = self.execute(ip, max_moves, &mut executed).map_err(|kind| {
+            self.ip = ip;
+            self.error(kind)
+        })?;
+        Ok((event, executed))
+    }
+
+    /// 戻るための記録を取りながら，`step_plain`と同じく命令を1つ実行する
+    fn step_recording(&mut self, max_moves: usize) -> Result<(Option<Event>, usize), RuntimeError> {
+        if !self.check_token_pointer() || self.needs_input() {
+            return self.step_plain(max_moves);
+        }
+
+        let ip = self.ip;
+        let undo = self.undo_for(ip, max_moves);
+        let Some(history) = &mut self.history else {
+            return self.step_plain(max_moves);
+        };
+        if history.needs_snapshot() {
+            history.snapshots.push_back(Snapshot {
+                step: history.steps(),
+                ip,
+                tape: self.tape.clone(),
+                input_position: self.input_position,
+                output_position: self.output_position,
+            });
+        }
+
+        let result = self.step_plain(max_moves)?;
+        if let Some(history) = &mut self.history {
+            history.push(undo);
+        }
+        Ok(result)
+    }
+
+    /// 直前に実行した命令を，実行する前に戻す．戻れなければ`false`
+    ///
+    /// テープ，ポインタ，命令の位置と読んだ入力は戻るが，出力は取り消せない
+    pub fn step_back(&mut self) -> bool {
+        match self.history.as_mut().and_then(History::pop) {
+            Some(undo) => {
+                self.undo(undo);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 記録の中で`breakpoint`が真になる位置の命令を最後に実行する前まで戻る
+    ///
+    /// 見つからなければ記録の始めまで戻って`false`を返す
+    pub fn run_back_to(&mut self, mut breakpoint: impl FnMut(usize) -> bool) -> bool {
+        let Some(history) = &self.history else {
+            return false;
+        };
+        let found = history.undos.iter().rposition(|undo| breakpoint(undo.ip));
+        let step = history.first_step + found.unwrap_or(0) as u64;
+        self.travel_back(step);
+        found.is_some()
+    }
+
+    /// 記録の`step`番目の命令を実行する前に戻る
+    fn travel_back(&mut self, step: u64) {
+        let Some(history) = &mut self.history else {
+            return;
+        };
+        // 1つずつ戻るより，保存したテープから実行し直す方が早ければそうする
+        let steps = history.steps();
+        let snapshot = history
+            .snapshots
+            .iter()
+            .rev()
+            .find(|snapshot| snapshot.step <= step)
+            .filter(|snapshot| step - snapshot.step < steps - step)
+            .cloned();
+        let Some(snapshot) = snapshot else {
+            while self.recorded_steps() > 0 && self.history.as_ref().unwrap().steps() > step {
+                self.step_back();
+            }
+            return;
+        };
+
+        // 保存した後に読んだ入力は入力に戻し，実行し直す時にもう一度読む
+        let start = (snapshot.step - history.first_step) as usize;
+        for byte in history
+            .undos
+            .range(start..)
+            .rev()
+            .filter_map(|undo| undo.input)
+        {
+            self.input.push_front(byte);
+        }
+        // `[>]`などは記録した時と同じだけ動かす
+        let replayed: Vec<usize> = history
+            .undos
+            .range(start..(step - history.first_step) as usize)
+            .map(|undo| undo.max_moves)
+            .collect();
+        history.truncate(snapshot.step);
+        self.tape = snapshot.tape;
+        self.ip = snapshot.ip;
+        self.input_position = snapshot.input_position;
+        self.output_position = snapshot.output_position;
+
+        // 実行し直す命令は一度成功しているので，出力以外のイベントは起きない
+        for max_moves in replayed {
+            if !matches!(
+                self.step_recording(max_moves),
+                Ok((None | Some(Event::Output(_)), _))
+            ) {
+                break;
+            }
+        }
+    }
+
+    /// `ip`の命令を実行する前の，戻るための記録
+    fn undo_for(&self, ip: usize, max_moves: usize) -> Undo {
+        let op = &self.code.vec()[ip];
+        let offsets = match *op {
+            Op::InclementValue(_) | Op::DecrementValue(_) | Op::Input | Op::Load(_) => vec![0],
+            Op::InclementValueAt { offset, .. }
+            | Op::DecrementValueAt { offset, .. }
+            | Op::InputAt { offset }
+            | Op::LoadAt { offset, .. } => vec![offset],
+            Op::SumRight(count) => vec![count as isize, 0],
+            Op::SumLeft(count) => vec![-(count as isize), 0],
+            Op::MultiplyAdd(ref targets) => targets
+                .iter()
+                .map(|&(offset, _)| offset)
+                .chain([0])
+                .collect(),
+            _ => Vec::new(),
+        };
+        // まだ使っていないセルは0
+        let cells = offsets
+            .into_iter()
+            .map(|offset| {
+                let position = self.tape.position_at(offset);
+                (position, self.tape.cell(position).unwrap_or(0))
+            })
+            .collect();
+        let input = match op {
+            Op::Input | Op::InputAt { .. } => self.input.front().copied(),
+            _ => None,
+        };
+
+        Undo {
+            ip,
+            position: self.tape.position(),
+            cells,
+            input,
+            max_moves,
+        }
+    }
+
+    fn undo(&mut self, undo: Undo) {
+        for &(position, value) in undo.cells.iter().rev() {
+            // 命令が触れずに伸びなかったセルは0のまま
+            if let Some(cell) = self.tape.cell_mut(position) {
+                *cell = value;
+            }
+        }
+        self.tape.set_position(undo.position);
+        self.ip = undo.ip;
+        if let Some(byte) = undo.input {
+            self.input.push_front(byte);
+            self.input_position -= 1;
+        }
+        if matches!(self.code.vec()[undo.ip], Op::Output | Op::OutputAt { .. }) {
+            self.output_position -= 1;
+        }
+    }
+
+    /// 直前に実行した出力の命令を，実行する前に戻す．出力先への書き込みに失敗した時に使う
+    pub(crate) fn rewind_output(&mut self) {
+        if !self.step_back() {
+            self.ip -= 1;
+            self.output_position -= 1;
+        }
+    }
+
+    /// 次に実行する命令の位置で起きたエラー
+    pub(crate) fn error(&self, kind: RuntimeErrorKind) -> RuntimeError {
+        RuntimeError {
+            ip: self.ip,
+            pointer: self.tape.pointer(),
+            kind,
+        }
+    }
+
+    fn needs_input(&self) -> bool {
+        matches!(self.code.vec()[self.ip], Op::Input | Op::InputAt { .. })
+            && self.input.is_empty()
+            && !self.input_closed
+    }
+
+    /// `ip`の命令を実行する．`[>]`などは動いたセルの数を`executed`に入れる
+    fn execute(
+        &mut self,
+        ip: usize,
+        max_moves: usize,
+        executed: &mut usize,
+    ) -> Result<Option<Event>, RuntimeErrorKind> {
+        match self.code.vec()[ip] {
+            Op::Output => return self.output(0).map(Some),
+            Op::OutputAt { offset } => return self.output(offset).map(Some),
+            Op::InclementPointer(count) => self.move_pointer(count as isize)?,
+            Op::DecrementPointer(count) => self.move_pointer(-(count as isize))?,
+            Op::InclementValue(count) => self.inclement_value(0, count)?,
+            Op::DecrementValue(count) => self.decrement_value(0, count)?,
+            Op::Input => self.input(0)?,
+            Op::LoopStart { if_zero_add } => self.loop_start(if_zero_add),
+            Op::LoopEnd { if_non_zero_sub } => self.loop_end(if_non_zero_sub),
+            Op::Load(n) => self.load(0, n)?,
+            Op::SumRight(count) => {
+                multiply_add(&mut self.tape, &self.cells, &[(count as isize, 1)])?
+            }
+            Op::SumLeft(count) => {
+                multiply_add(&mut self.tape, &self.cells, &[(-(count as isize), 1)])?
+            }
+            Op::MultiplyAdd(ref targets) => multiply_add(&mut self.tape, &self.cells, targets)?,
+            Op::JumpZeroRight { per } => {
+                *executed = self.jump_zero(per as isize, max_moves)?.max(1);
+            }
+            Op::JumpZeroLeft { per } => {
+                *executed = self.jump_zero(-(per as isize), max_moves)?.max(1);
+            }
+            Op::InclementValueAt { offset, count } => self.inclement_value(offset, count)?,
+            Op::DecrementValueAt { offset, count } => self.decrement_value(offset, count)?,
+            Op::InputAt { offset } => self.input(offset)?,
+            Op::LoadAt { offset, n } => self.load(offset, n)?,
+        }
+        Ok(None)
+    }
+
+    fn check_token_pointer(&self) -> bool {
+        self.ip < self.code.vec().len()
+    }
+
+    fn move_pointer(&mut self, diff: isize) -> Result<(), RuntimeErrorKind> {
+        self.tape.move_pointer(diff)
+    }
+
+    fn inclement_value(&mut self, offset: isize, count: usize) -> Result<(), RuntimeErrorKind> {
+        self.add_value(offset, count as i128)
+    }
+
+    fn decrement_value(&mut self, offset: isize, count: usize) -> Result<(), RuntimeErrorKind> {
+        self.add_value(offset, -(count as i128))
+    }
+
+    fn add_value(&mut self, offset: isize, diff: i128) -> Result<(), RuntimeErrorKind> {
+        let cell = self.tape.at(offset)?;
+        *cell = self
+            .cells
+            .add(*cell, diff)
+            .ok_or(RuntimeErrorKind::CellOverflow)?;
+        Ok(())
+    }
+
+    fn output(&mut self, offset: isize) -> Result<Event, RuntimeErrorKind> {
+        // 8ビットより広いセルは下位8ビットを出力する
+        let byte = *self.tape.at(offset)? as u8;
+        self.output_position += 1;
+        Ok(Event::Output(byte))
+    }
+
+    /// 入力を1バイト読む．入力がなければ`close_input`の後なので，`EofPolicy`に従う
+    fn input(&mut self, offset: isize) -> Result<(), RuntimeErrorKind> {
+        let cell = self.tape.at(offset)?;
+        match self.input.pop_front() {
+            Some(byte) => {
+                *cell = byte as u32;
+                self.input_position += 1;
+            }
+            None => match self.eof {
+                EofPolicy::Error => return Err(RuntimeErrorKind::UnexpectedEof),
+                EofPolicy::Unchanged => {}
+                EofPolicy::Zero => *cell = 0,
+                EofPolicy::MinusOne => *cell = self.cells.width.max(),
+            },
+        }
+        Ok(())
+    }
+
+    fn loop_start(&mut self, if_zero_add: usize) {
+        if self.tape.get() != 0 {
+            return;
+        }
+
+        self.ip += if_zero_add;
+    }
+
+    fn loop_end(&mut self, if_non_zero_sub: usize) {
+        if self.tape.get() == 0 {
+            return;
+        }
+
+        self.ip -= if_non_zero_sub;
+    }
+
+    fn load(&mut self, offset: isize, n: u32) -> Result<(), RuntimeErrorKind> {
+        *self.tape.at(offset)? = n;
+        Ok(())
+    }
+
+    /// `[>]`や`[<<]`と同じく，0のセルが見つかるまでper毎に移動する
+    ///
+    /// `max_moves`回動いても見つからなければ，続きから探せるように命令の位置を戻す．動いた回数を返す
+    fn jump_zero(&mut self, per: isize, max_moves: usize) -> Result<usize, RuntimeErrorKind> {
+        let mut moves = 0;
+        while self.tape.get() != 0 {
+            if moves == max_moves {
+                self.ip -= 1;
+                break;
+            }
+            self.move_pointer(per)?;
+            moves += 1;
+        }
+        Ok(moves)
+    }
+}
+
+/// `[->+++>--<<]`と同じく，係数を掛けて加えた後に現在のセルを0にする
+fn multiply_add(
+    tape: &mut Tape,
+    cells: &CellConfig,
+    targets: &[(isize, isize)],
+) -> Result<(), RuntimeErrorKind> {
+    let value = tape.get();
+    // ループの本体が実行されない場合は，移動先のセルに触れない
+    if value == 0 {
+        return Ok(());
+    }
+    for &(offset, factor) in targets {
+        let cell = tape.at(offset)?;
+        *cell = cells
+            .multiply_add(*cell, value, factor)
+            .ok_or(RuntimeErrorKind::CellOverflow)?;
+    }
+    *tape.at(0)? = 0;
+    Ok(())
+}
+
+/// 内側のループで使う，`CellConfig::add`と同じ結果になる増減
+#[derive(Debug, Clone, Copy)]
+struct Arith {
+    max: u32,
+    checked: bool,
+}
+
+impl Arith {
+    fn new(cells: &CellConfig) -> Self {
+        Self {
+            max: cells.width.max(),
+            checked: !cells.is_wrapping(),
+        }
+    }
+
+    #[inline(always)]
+    fn add(self, value: u32, count: usize) -> Option<u32> {
+        if self.checked {
+            let sum = (value as u64).checked_add(count as u64)?;
+            (sum <= self.max as u64).then_some(sum as u32)
+        } else {
+            // 幅は32ビット以下なので，下位32ビットで足してから切り詰めればよい
+            Some(value.wrapping_add(count as u32) & self.max)
+        }
+    }
+
+    #[inline(always)]
+    fn sub(self, value: u32, count: usize) -> Option<u32> {
+        if self.checked {
+            (count as u64 <= value as u64).then(|| value - count as u32)
+        } else {
+            Some(value.wrapping_sub(count as u32) & self.max)
+        }
+    }
+}
+
+/// `multiply_add`を今あるセルの中で実行する．テープを伸ばすか範囲外への増減になるなら，何もせずに`false`を返す
+#[inline(always)]
+fn multiply_add_in<A: Addressing>(
+    cells: &mut [u32],
+    pointer: usize,
+    config: &CellConfig,
+    targets: &[(isize, isize)],
+) -> bool {
+    let value = cells[pointer];
+    if value == 0 {
+        return true;
+    }
+    // 途中で止まらないように，書き換える前に全ての移動先を確かめる．
+    // 移動先が重なるテープでは前の書き換えで結果が変わるので，範囲外への増減は調べずに任せる
+    let len = cells.len();
+    for &(offset, factor) in targets {
+        let Some(index) = A::index(len, pointer, offset) else {
+            return false;
+        };
+        if !config.is_wrapping()
+            && (A::ALIASES || config.multiply_add(cells[index], value, factor).is_none())
+        {
+            return false;
+        }
+    }
+    for &(offset, factor) in targets {
+        let index = A::index(len, pointer, offset).unwrap();
+        cells[index] = config.wrap(cells[index] as i128 + value as i128 * factor as i128);
+    }
+    cells[pointer] = 0;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn yields_on_input_and_output() {
+        // ,+.,.
+        let mut machine = Machine::new(OpCode::new(vec![
+            Op::Input,
+            Op::InclementValue(1),
+            Op::Output,
+            Op::Input,
+            Op::Output,
+        ]))
+        .with_eof(EofPolicy::Zero);
+
+        assert_eq!(machine.resume().unwrap(), Event::NeedInput);
+        assert_eq!(machine.resume().unwrap(), Event::NeedInput);
+        machine.provide_input(b"a");
+        assert_eq!(machine.resume().unwrap(), Event::Output(b'b'));
+        assert_eq!(machine.resume().unwrap(), Event::NeedInput);
+        machine.close_input();
+        assert_eq!(machine.resume().unwrap(), Event::Output(0));
+        assert_eq!(machine.resume().unwrap(), Event::Halted);
+        assert_eq!(machine.resume().unwrap(), Event::Halted);
+    }
+
+    #[test]
+    fn fuel_exhausted_can_resume() {
+        let mut machine = Machine::new(OpCode::new(vec![Op::InclementValue(1); 5]))
+            .with_budget(Budget::unlimited().with_steps(2));
+
+        assert_eq!(
+            machine.resume().unwrap(),
+            Event::FuelExhausted(Limit::Steps)
+        );
+        assert_eq!(machine.ip(), 2);
+
+        machine.budget_mut().steps = Some(10);
+        assert_eq!(machine.resume().unwrap(), Event::Halted);
+        assert_eq!(machine.memory()[0], 5);
+        assert_eq!(machine.budget().steps, Some(7));
+    }
+
+    /// 終わるかエラーになるまで`next`で進め，出力，終わり方と最後のテープを返す
+    fn finish(
+        mut machine: Machine,
+        mut next: impl FnMut(&mut Machine) -> Result<Option<Event>, RuntimeError>,
+    ) -> (Vec<u8>, String, Vec<u32>, isize) {
+        machine.close_input();
+        let mut output = Vec::new();
+        let end = loop {
+            match next(&mut machine) {
+                Ok(Some(Event::Output(byte))) => output.push(byte),
+                Ok(Some(Event::Halted)) => break "halted".to_string(),
+                Ok(_) => {}
+                Err(e) => break e.to_string(),
+            }
+        };
+        let tape = machine.tape();
+        (output, end, tape.cells().to_vec(), tape.position())
+    }
+
+    #[test]
+    fn resume_runs_like_steps() {
+        use ast::cell::{CellWidth, Overflow};
+        use ast::inst::{Ast, AstCode};
+
+        let programs = [
+            // +++[->++>---<<]>>>>.<<<<<<+.
+            vec![
+                Ast::InclementValue(3),
+                Ast::MultiplyAdd(vec![(1, 2), (2, -3)]),
+                Ast::InclementPointer(4),
+                Ast::Output,
+                Ast::DecrementPointer(6),
+                Ast::InclementValue(1),
+                Ast::Output,
+            ],
+            // ++++[->++++<]>[->++++<]>[->++++<]>.+<<[<]+>[>]>+.
+            vec![
+                Ast::InclementValue(4),
+                Ast::MultiplyAdd(vec![(1, 4)]),
+                Ast::InclementPointer(1),
+                Ast::MultiplyAdd(vec![(1, 4)]),
+                Ast::InclementPointer(1),
+                Ast::MultiplyAdd(vec![(1, 4)]),
+                Ast::InclementPointer(1),
+                Ast::Output,
+                Ast::InclementValue(1),
+                Ast::DecrementPointer(2),
+                Ast::JumpZeroLeft { per: 1 },
+                Ast::InclementValue(1),
+                Ast::InclementPointer(1),
+                Ast::JumpZeroRight { per: 1 },
+                Ast::InclementPointer(1),
+                Ast::InclementValue(1),
+                Ast::Output,
+            ],
+            // ++[>+++[->>+<<]>>[-<+>]<[->+<]<<-]>--[>>+<<++]
+            vec![
+                Ast::InclementValue(2),
+                Ast::Loop(AstCode::new(vec![
+                    Ast::InclementPointer(1),
+                    Ast::InclementValue(3),
+                    Ast::SumRight(2),
+                    Ast::InclementPointer(2),
+                    Ast::SumLeft(1),
+                    Ast::DecrementPointer(1),
+                    Ast::SumRight(1),
+                    Ast::DecrementPointer(2),
+                    Ast::DecrementValue(1),
+                ])),
+                Ast::InclementPointer(1),
+                Ast::DecrementValue(2),
+                Ast::Loop(AstCode::new(vec![
+                    Ast::InclementPointer(2),
+                    Ast::InclementValue(1),
+                    Ast::DecrementPointer(2),
+                    Ast::InclementValue(2),
+                ])),
+            ],
+            // 200回+の後の[->+>>>+<<<<]．長さ3の端が繋がったテープでは同じセルに2回加える
+            vec![
+                Ast::InclementValue(200),
+                Ast::MultiplyAdd(vec![(1, 1), (4, 1)]),
+                Ast::InclementPointer(1),
+                Ast::Output,
+            ],
+        ];
+        let tapes = [
+            TapeConfig::Fixed(4),
+            TapeConfig::GrowRight(1),
+            TapeConfig::Unbounded,
+            TapeConfig::Circular(3),
+        ];
+        let cells = [
+            CellConfig::default(),
+            CellConfig::new(CellWidth::U8, Overflow::Checked),
+        ];
+
+        for program in programs {
+            let code: OpCode = AstCode::new(program).into();
+            for tape in tapes {
+                for cells in cells {
+                    let machine = || Machine::new(code.clone()).with_tape(tape).with_cells(cells);
+                    let expected = finish(machine(), Machine::step);
+                    assert_eq!(
+                        finish(machine(), |machine| machine.resume().map(Some)),
+                        expected,
+                        "{:?} {:?} {:?}",
+                        code,
+                        tape,
+                        cells
+                    );
+                    // 制限で細かく止めても同じになる
+                    let budget = Budget::unlimited().with_steps(3);
+                    let refill = |machine: &mut Machine| match machine.resume() {
+                        Ok(Event::FuelExhausted(_)) => {
+                            machine.budget_mut().steps = Some(3);
+                            Ok(None)
+                        }
+                        result => result.map(Some),
+                    };
+                    assert_eq!(
+                        finish(machine().with_budget(budget), refill),
+                        expected,
+                        "{:?} {:?} {:?}",
+                        code,
+                        tape,
+                        cells
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn jump_zero_counts_moves() {
+        // +[>] は端が繋がった1セルのテープでは終わらない
+        let code = OpCode::new(vec![Op::InclementValue(1), Op::JumpZeroRight { per: 1 }]);
+        let mut machine = Machine::new(code)
+            .with_tape(TapeConfig::Circular(1))
+            .with_budget(
+                Budget::unlimited()
+                    .with_steps(1000)
+                    .with_time(Duration::from_millis(100)),
+            );
+
+        assert_eq!(
+            machine.resume().unwrap(),
+            Event::FuelExhausted(Limit::Steps)
+        );
+        assert_eq!(machine.ip(), 1);
+
+        *machine.budget_mut() = Budget::unlimited().with_time(Duration::from_millis(10));
+        assert_eq!(machine.resume().unwrap(), Event::FuelExhausted(Limit::Time));
+        machine.step().unwrap();
+        assert_eq!(machine.ip(), 1);
+
+        // 途中で止まっても，続きから探す
+        let code = OpCode::new(vec![
+            Op::InclementValue(1),
+            Op::MultiplyAdd(vec![(1, 1), (2, 1), (3, 1), (4, 1)]),
+            Op::InclementValue(1),
+            Op::JumpZeroRight { per: 1 },
+            Op::Output,
+        ]);
+        let mut machine = Machine::new(code).with_budget(Budget::unlimited().with_steps(5));
+        assert_eq!(
+            machine.resume().unwrap(),
+            Event::FuelExhausted(Limit::Steps)
+        );
+        assert_eq!((machine.ip(), machine.pointer()), (3, 2));
+        machine.budget_mut().steps = Some(10);
+        assert_eq!(machine.resume().unwrap(), Event::Output(0));
+        assert_eq!(machine.pointer(), 5);
+        assert_eq!(machine.budget().steps, Some(6));
+    }
+
+    #[test]
+    fn budget_counts_like_single_steps() {
+        // ++++[>+++[>++[>]<-]<-]>>+[<]. の命令の数を，記録して1命令ずつ実行した場合と比べる
+        let code = OpCode::new(vec![
+            Op::InclementValue(4),
+            Op::LoopStart { if_zero_add: 14 },
+            Op::InclementPointer(1),
+            Op::InclementValue(3),
+            Op::LoopStart { if_zero_add: 8 },
+            Op::InclementPointer(1),
+            Op::InclementValue(2),
+            Op::JumpZeroRight { per: 1 },
+            Op::DecrementPointer(1),
+            Op::DecrementValue(1),
+            Op::DecrementPointer(1),
+            Op::DecrementValue(1),
+            Op::LoopEnd { if_non_zero_sub: 8 },
+            Op::DecrementPointer(1),
+            Op::DecrementValue(1),
+            Op::LoopEnd {
+                if_non_zero_sub: 14,
+            },
+            Op::InclementPointer(2),
+            Op::InclementValue(1),
+            Op::JumpZeroLeft { per: 1 },
+            Op::Output,
+        ]);
+        let run = |steps: usize, recording: Option<Recording>| {
+            let mut machine = Machine::new(code.clone())
+                .with_budget(Budget::unlimited().with_steps(steps).with_output(0));
+            if let Some(recording) = recording {
+                machine = machine.with_recording(recording);
+            }
+            let event = machine.resume().unwrap();
+            (
+                event,
+                *machine.budget(),
+                machine.ip(),
+                machine.pointer(),
+                machine.memory().to_vec(),
+            )
+        };
+
+        for steps in [1, 37, 1000] {
+            let plain = run(steps, None);
+            assert_eq!(plain, run(steps, Some(Recording::default())), "{}", steps);
+        }
+        let (event, budget, ..) = run(1000, None);
+        assert_eq!(event, Event::FuelExhausted(Limit::Output));
+        assert!(budget.steps.unwrap() < 1000);
+    }
+
+    #[test]
+    fn travels_back_to_input_and_output_positions() {
+        // ,.を8回繰り返す
+        let code = OpCode::new((0..8).flat_map(|_| [Op::Input, Op::Output]).collect());
+        for snapshot_interval in [2, 100] {
+            let recording = Recording {
+                capacity: 100,
+                snapshot_interval,
+            };
+            let mut machine = Machine::new(code.clone()).with_recording(recording);
+            machine.provide_input(b"abcdefgh");
+            while !machine.is_finished() {
+                machine.step().unwrap();
+            }
+            assert_eq!(
+                (machine.input_position(), machine.output_position()),
+                (8, 8)
+            );
+
+            // 6回目の,を実行する前は，5バイト読んで5バイト出力している
+            assert!(machine.run_back_to(|ip| ip == 10));
+            assert_eq!(machine.ip(), 10);
+            assert_eq!(
+                (machine.input_position(), machine.output_position()),
+                (5, 5),
+                "{}",
+                snapshot_interval
+            );
+            assert_eq!(machine.resume().unwrap(), Event::Output(b'f'));
+            assert_eq!(
+                (machine.input_position(), machine.output_position()),
+                (6, 6)
+            );
+        }
+    }
+
+    #[test]
+    fn travels_back_through_snapshots() {
+        // +++[->+<]>[-<++>]<. を小さな記録で実行する
+        let code = OpCode::new(vec![
+            Op::InclementValue(3),
+            Op::LoopStart { if_zero_add: 5 },
+            Op::DecrementValue(1),
+            Op::InclementPointer(1),
+            Op::InclementValue(1),
+            Op::DecrementPointer(1),
+            Op::LoopEnd { if_non_zero_sub: 5 },
+            Op::InclementPointer(1),
+            Op::MultiplyAdd(vec![(-1, 2)]),
+            Op::DecrementPointer(1),
+            Op::Output,
+        ]);
+        let recording = Recording {
+            capacity: 12,
+            snapshot_interval: 4,
+        };
+        let mut machine = Machine::new(code).with_recording(recording);
+
+        // 各命令を実行する前の状態を覚えておく
+        let mut states = Vec::new();
+        while !machine.is_finished() {
+            states.push((
+                machine.ip(),
+                machine.pointer(),
+                machine.memory()[..2].to_vec(),
+            ));
+            machine.step().unwrap();
+        }
+        assert_eq!(machine.memory()[0], 6);
+        assert_eq!(machine.recorded_steps(), 12);
+
+        assert!(machine.run_back_to(|ip| ip == 4));
+        let state = (
+            machine.ip(),
+            machine.pointer(),
+            machine.memory()[..2].to_vec(),
+        );
+        assert_eq!(state, (4, 1, vec![0, 2]));
+        assert!(states.contains(&state));
+
+        // 記録より前には戻れない
+        assert!(!machine.run_back_to(|ip| ip == 0));
+        let oldest = states.len() - 12;
+        let state = (
+            machine.ip(),
+            machine.pointer(),
+            machine.memory()[..2].to_vec(),
+        );
+        assert_eq!(state, states[oldest]);
+        assert!(!machine.step_back());
+
+        while !machine.is_finished() {
+            machine.step().unwrap();
+        }
+        assert_eq!(machine.memory()[..2], [6, 0]);
+    }
+}
@@ -0,0 +1,320 @@
+pub use ast::tape::{TapeConfig, DEFAULT_MAX_LEN};
+
+use crate::error::RuntimeErrorKind;
+
+/// インタプリタのテープ
+///
+/// 左に伸びた分は`origin`に数える．`pointer`は`cells`の添字
+#[derive(Debug, Clone)]
+pub struct Tape {
+    config: TapeConfig,
+    cells: Vec<u32>,
+    pointer: usize,
+    origin: usize,
+    /// 伸びるテープの`cells`の長さの上限
+    max_len: usize,
+}
+
+impl Tape {
+    pub fn new(config: TapeConfig) -> Self {
+        let len = match config {
+            TapeConfig::Fixed(len) | TapeConfig::Circular(len) => {
+                assert!(len > 0, "tape must not be empty");
+                len
+            }
+            TapeConfig::GrowRight(len) => len.max(1),
+            TapeConfig::Unbounded => 64,
+        };
+
+        Self {
+            config,
+            cells: vec![0; len],
+            pointer: 0,
+            origin: 0,
+            max_len: DEFAULT_MAX_LEN,
+        }
+    }
+
+    /// 伸びるテープの長さの上限を変える．越えて伸ばそうとすると`TapeLimitExceeded`になる
+    pub fn with_max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    pub fn config(&self) -> TapeConfig {
+        self.config
+    }
+
+    /// 今までに使ったセル
+    pub fn cells(&self) -> &[u32] {
+        &self.cells
+    }
+
+    /// `cells()`での現在のセルの添字
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    /// 始めのセルを0とした現在のセルの位置．`Unbounded`では負になることがある
+    pub fn position(&self) -> isize {
+        self.pointer as isize - self.origin as isize
+    }
+
+    pub fn get(&self) -> u32 {
+        self.cells[self.pointer]
+    }
+
+    /// 始めのセルを0とした位置`position`のセル．まだ使っていないセルは`None`
+    pub fn cell(&self, position: isize) -> Option<u32> {
+        self.index_of(position).map(|index| self.cells[index])
+    }
+
+    /// 始めのセルを0とした位置`position`のセル．テープは伸ばさない
+    pub fn cell_mut(&mut self, position: isize) -> Option<&mut u32> {
+        self.index_of(position).map(|index| &mut self.cells[index])
+    }
+
+    /// 左に伸ばしたセルの数．`cells()`での位置0のセルの添字
+    pub fn origin(&self) -> usize {
+        self.origin
+    }
+
+    /// 保存したテープを組み立てる．形に合わないか，添字がテープの外にあれば`None`
+    pub(crate) fn from_parts(
+        config: TapeConfig,
+        cells: Vec<u32>,
+        pointer: usize,
+        origin: usize,
+    ) -> Option<Self> {
+        let fits = match config {
+            TapeConfig::Fixed(len) | TapeConfig::Circular(len) => cells.len() == len,
+            TapeConfig::GrowRight(_) | TapeConfig::Unbounded => !cells.is_empty(),
+        };
+        let origin_fits = origin == 0 || config == TapeConfig::Unbounded;
+        (fits && origin_fits && pointer < cells.len() && origin < cells.len()).then_some(Self {
+            config,
+            cells,
+            pointer,
+            origin,
+            max_len: DEFAULT_MAX_LEN,
+        })
+    }
+
+    /// 内側のループでまとめて書き換えるための，今までに使ったセルと`cells()`での現在のセルの添字
+    pub(crate) fn parts_mut(&mut self) -> (&mut [u32], &mut usize) {
+        (&mut self.cells, &mut self.pointer)
+    }
+
+    /// 現在のセルから相対位置`offset`にあるセルの位置．テープは伸ばさない
+    pub fn position_at(&self, offset: isize) -> isize {
+        match self.config {
+            TapeConfig::Circular(len) => (self.position() + offset).rem_euclid(len as isize),
+            _ => self.position() + offset,
+        }
+    }
+
+    /// ポインタを位置`position`に戻す．記録から戻る時に使うので，既に使ったセルに限る
+    pub(crate) fn set_position(&mut self, position: isize) {
+        self.pointer = self
+            .index_of(position)
+            .expect("restored position must be on the tape");
+    }
+
+    fn index_of(&self, position: isize) -> Option<usize> {
+        position
+            .checked_add_unsigned(self.origin)
+            .and_then(|index| usize::try_from(index).ok())
+            .filter(|&index| index < self.cells.len())
+    }
+
+    /// 現在のセルから相対位置`offset`にあるセル．必要ならテープを伸ばす
+    pub fn at(&mut self, offset: isize) -> Result<&mut u32, RuntimeErrorKind> {
+        let index = self.index(offset)?;
+        Ok(&mut self.cells[index])
+    }
+
+    /// 現在のセルから相対位置`offset`にあるセルの添字．必要ならテープを伸ばす
+    ///
+    /// 左に伸ばすと添字がずれるので，続けて使う添字は伸ばした後に求め直す
+    pub fn index(&mut self, offset: isize) -> Result<usize, RuntimeErrorKind> {
+        let len = self.cells.len();
+        match self.config {
+            TapeConfig::Circular(_) => {
+                Ok((self.pointer as isize + offset).rem_euclid(len as isize) as usize)
+            }
+            TapeConfig::Fixed(_) => match self.pointer.checked_add_signed(offset) {
+                Some(index) if index < len => Ok(index),
+                Some(_) => Err(RuntimeErrorKind::PointerOverflow),
+                None => Err(RuntimeErrorKind::PointerUnderflow),
+            },
+            TapeConfig::GrowRight(_) => {
+                let index = self
+                    .pointer
+                    .checked_add_signed(offset)
+                    .ok_or(RuntimeErrorKind::PointerUnderflow)?;
+                self.grow_right(index)?;
+                Ok(index)
+            }
+            TapeConfig::Unbounded => match self.pointer.checked_add_signed(offset) {
+                Some(index) => {
+                    self.grow_right(index)?;
+                    Ok(index)
+                }
+                None => {
+                    // 足りない分と今の長さの大きい方だけ，上限までで左に伸ばす
+                    let shortage = offset.unsigned_abs() - self.pointer;
+                    if shortage > self.max_len.saturating_sub(len) {
+                        return Err(RuntimeErrorKind::TapeLimitExceeded);
+                    }
+                    let extra = shortage.max(len).min(self.max_len - len);
+                    self.cells.splice(0..0, std::iter::repeat_n(0, extra));
+                    self.pointer += extra;
+                    self.origin += extra;
+                    Ok(extra - shortage)
+                }
+            },
+        }
+    }
+
+    /// ポインタを`diff`だけ動かす
+    pub fn move_pointer(&mut self, diff: isize) -> Result<(), RuntimeErrorKind> {
+        self.pointer = self.index(diff)?;
+        Ok(())
+    }
+
+    fn grow_right(&mut self, index: usize) -> Result<(), RuntimeErrorKind> {
+        if index >= self.cells.len() {
+            if index >= self.max_len {
+                return Err(RuntimeErrorKind::TapeLimitExceeded);
+            }
+            let len = (index + 1).max(self.cells.len() * 2).min(self.max_len);
+            self.cells.resize(len, 0);
+        }
+        Ok(())
+    }
+}
+
+/// テープの形ごとの添字の求め方．実行器の内側のループをテープの形ごとに作るのに使う
+pub(crate) trait Addressing {
+    /// 離れた相対位置が同じセルになることがあるか
+    const ALIASES: bool;
+
+    /// 長さ`len`のセルの`pointer`から相対位置`offset`にあるセルの添字
+    ///
+    /// テープを伸ばす必要があるか，範囲外なら`None`
+    fn index(len: usize, pointer: usize, offset: isize) -> Option<usize>;
+}
+
+/// 端が繋がっていないテープ．今あるセルの中だけを扱い，伸ばすかどうかは`Tape::index`に任せる
+pub(crate) struct Linear;
+
+impl Addressing for Linear {
+    const ALIASES: bool = false;
+
+    #[inline(always)]
+    fn index(len: usize, pointer: usize, offset: isize) -> Option<usize> {
+        // 0より左は大きな数になるので，比較は1回で済む
+        let index = pointer.wrapping_add_signed(offset);
+        (index < len).then_some(index)
+    }
+}
+
+/// 端が反対側の端に繋がったテープ
+pub(crate) struct Circular;
+
+impl Addressing for Circular {
+    const ALIASES: bool = true;
+
+    #[inline(always)]
+    fn index(len: usize, pointer: usize, offset: isize) -> Option<usize> {
+        // 端を越えない時は割り算をしない
+        let index = pointer.wrapping_add_signed(offset);
+        if index < len {
+            return Some(index);
+        }
+        Some((pointer as isize + offset).rem_euclid(len as isize) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_tape_reports_both_ends() {
+        let mut tape = Tape::new(TapeConfig::Fixed(4));
+        assert!(matches!(
+            tape.move_pointer(-1),
+            Err(RuntimeErrorKind::PointerUnderflow)
+        ));
+        tape.move_pointer(3).unwrap();
+        assert!(matches!(
+            tape.move_pointer(1),
+            Err(RuntimeErrorKind::PointerOverflow)
+        ));
+        assert_eq!(tape.pointer(), 3);
+    }
+
+    #[test]
+    fn grow_right_tape_extends() {
+        let mut tape = Tape::new(TapeConfig::GrowRight(2));
+        tape.move_pointer(10).unwrap();
+        assert!(tape.cells().len() > 10);
+        assert!(matches!(
+            tape.move_pointer(-11),
+            Err(RuntimeErrorKind::PointerUnderflow)
+        ));
+    }
+
+    #[test]
+    fn unbounded_tape_extends_left() {
+        let mut tape = Tape::new(TapeConfig::Unbounded);
+        *tape.at(0).unwrap() = 7;
+        tape.move_pointer(-100).unwrap();
+        assert_eq!(tape.position(), -100);
+        let index = tape.index(100).unwrap();
+        assert_eq!(tape.cells()[index], 7);
+        assert_eq!(tape.cell(0), Some(7));
+        *tape.cell_mut(-100).unwrap() = 3;
+        assert_eq!(tape.get(), 3);
+        assert_eq!(tape.cell(100_000), None);
+    }
+
+    #[test]
+    fn growing_tapes_stop_at_max_len() {
+        let mut tape = Tape::new(TapeConfig::GrowRight(2)).with_max_len(10);
+        tape.move_pointer(9).unwrap();
+        assert_eq!(tape.cells().len(), 10);
+        assert!(matches!(
+            tape.move_pointer(1),
+            Err(RuntimeErrorKind::TapeLimitExceeded)
+        ));
+        assert_eq!(tape.pointer(), 9);
+
+        let mut tape = Tape::new(TapeConfig::Unbounded).with_max_len(100);
+        tape.move_pointer(-36).unwrap();
+        assert_eq!(tape.cells().len(), 100);
+        assert!(matches!(
+            tape.move_pointer(-1),
+            Err(RuntimeErrorKind::TapeLimitExceeded)
+        ));
+        assert!(matches!(
+            tape.move_pointer(100),
+            Err(RuntimeErrorKind::TapeLimitExceeded)
+        ));
+        assert_eq!(tape.position(), -36);
+    }
+
+    #[test]
+    fn circular_tape_wraps() {
+        let mut tape = Tape::new(TapeConfig::Circular(5));
+        tape.move_pointer(-1).unwrap();
+        assert_eq!(tape.pointer(), 4);
+        tape.move_pointer(3).unwrap();
+        assert_eq!(tape.pointer(), 2);
+    }
+}
@@ -0,0 +1,229 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Result};
+use ast::cell::CellConfig;
+use ast::eof::EofPolicy;
+use ast::opt::{named_pass, OptLevel, Optimizer};
+use bytecode_backend::tape::{TapeConfig, DEFAULT_MAX_LEN};
+use parser::dialect::Dialect;
+
+/// ファイル名の前に置くサブコマンド
+const SUBCOMMANDS: [&str; 3] = ["fmt", "minify", "debug"];
+
+/// コマンドライン引数
+#[derive(Debug)]
+pub struct Args {
+    pub command: Command,
+    /// ソースコードの綴り
+    pub dialect: Dialect,
+    /// `fmt`の1行の最大文字数
+    pub width: Option<usize>,
+    /// `-O0`から`-O3`
+    pub opt_level: OptLevel,
+    /// `--enable-pass`で加えるパス
+    pub enabled_passes: Vec<String>,
+    /// `--disable-pass`で取り除くパス
+    pub disabled_passes: Vec<String>,
+    /// パスごとのノード数を標準エラー出力に書き出すか
+    pub pass_stats: bool,
+    /// REPL，デバッガと，状態を保存しながら実行する時のテープの形
+    pub tape: TapeConfig,
+    /// 伸びるテープの長さの上限
+    pub max_tape_len: usize,
+    /// 入力が終わった後の`,`の扱い
+    pub eof: EofPolicy,
+    /// `--cell`と`--overflow`で決めるセルの幅と折り返し
+    pub cells: CellConfig,
+    /// インタプリタで実行し，この命令数ごとに状態を保存する
+    pub checkpoint_every: Option<usize>,
+    /// インタプリタで実行し，保存した状態から続ける
+    pub resume: Option<PathBuf>,
+}
+
+/// サブコマンド
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    /// REPLを起動する
+    Repl,
+    /// ファイルをコンパイルして実行する
+    Compile(PathBuf),
+    /// ファイルを整形して出力する
+    Fmt(PathBuf),
+    /// コメントと冗長な命令を取り除いて出力する
+    Minify(PathBuf),
+    /// ファイルをデバッガで実行する
+    Debug(PathBuf),
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut subcommand = None;
+        let mut file = None;
+        let mut dialect = Dialect::brainfuck();
+        let mut width = None;
+        let mut opt_level = OptLevel::default();
+        let mut enabled_passes = Vec::new();
+        let mut disabled_passes = Vec::new();
+        let mut pass_stats = false;
+        let mut tape = TapeConfig::default();
+        let mut max_tape_len = DEFAULT_MAX_LEN;
+        let mut eof = EofPolicy::default();
+        let mut cells = CellConfig::default();
+        let mut checkpoint_every = None;
+        let mut resume = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if let Some(value) = option_value("--dialect", &arg, &mut args)? {
+                dialect = load_dialect(&value)?;
+            } else if let Some(value) = option_value("--width", &arg, &mut args)? {
+                width = Some(
+                    value
+                        .parse()
+                        .map_err(|_| anyhow!("invalid width: {}", value))?,
+                );
+            } else if let Some(value) = option_value("--enable-pass", &arg, &mut args)? {
+                check_pass_name(&value)?;
+                enabled_passes.push(value);
+            } else if let Some(value) = option_value("--disable-pass", &arg, &mut args)? {
+                check_pass_name(&value)?;
+                disabled_passes.push(value);
+            } else if let Some(value) = option_value("--tape", &arg, &mut args)? {
+                tape = value.parse().map_err(|e: String| anyhow!(e))?;
+            } else if let Some(value) = option_value("--max-tape-len", &arg, &mut args)? {
+                max_tape_len = value
+                    .parse()
+                    .ok()
+                    .filter(|&len| len > 0)
+                    .ok_or_else(|| anyhow!("invalid tape length: {}", value))?;
+            } else if let Some(value) = option_value("--eof", &arg, &mut args)? {
+                eof = value.parse().map_err(|e: String| anyhow!(e))?;
+            } else if let Some(value) = option_value("--cell", &arg, &mut args)? {
+                cells.width = value.parse().map_err(|e: String| anyhow!(e))?;
+            } else if let Some(value) = option_value("--overflow", &arg, &mut args)? {
+                cells.overflow = value.parse().map_err(|e: String| anyhow!(e))?;
+            } else if let Some(value) = option_value("--checkpoint-every", &arg, &mut args)? {
+                checkpoint_every = Some(
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|&steps| steps > 0)
+                        .ok_or_else(|| anyhow!("invalid checkpoint interval: {}", value))?,
+                );
+            } else if let Some(value) = option_value("--resume", &arg, &mut args)? {
+                resume = Some(PathBuf::from(value));
+            } else if arg == "--pass-stats" {
+                pass_stats = true;
+            } else if let Some(level) = arg.strip_prefix("-O") {
+                opt_level = level.parse().map_err(|e: String| anyhow!(e))?;
+            } else if arg.starts_with('-') {
+                bail!("unknown option: {}", arg);
+            } else if subcommand.is_none() && file.is_none() && SUBCOMMANDS.contains(&arg.as_str())
+            {
+                subcommand = Some(arg);
+            } else if file.replace(PathBuf::from(arg)).is_some() {
+                bail!("too many input files");
+            }
+        }
+
+        let command = match (subcommand.as_deref(), file) {
+            (None, None) => Command::Repl,
+            (None, Some(file)) => Command::Compile(file),
+            (Some("fmt"), Some(file)) => Command::Fmt(file),
+            (Some("minify"), Some(file)) => Command::Minify(file),
+            (Some("debug"), Some(file)) => Command::Debug(file),
+            (Some(subcommand), None) => bail!("missing input file for {}", subcommand),
+            (Some(subcommand), Some(_)) => unreachable!("unknown subcommand: {}", subcommand),
+        };
+        if (checkpoint_every.is_some() || resume.is_some())
+            && !matches!(command, Command::Compile(_))
+        {
+            bail!("--checkpoint-every and --resume need an input file to run");
+        }
+        // LLVMでコンパイルしたプログラムのテープは長さ30000で固定なので，他のテープはインタプリタでしか使えない
+        let native = matches!(command, Command::Compile(_))
+            && checkpoint_every.is_none()
+            && resume.is_none();
+        if native && tape != TapeConfig::default() {
+            bail!(
+                "--tape {} is not supported when compiling with LLVM; use debug, the REPL, --checkpoint-every or --resume",
+                tape
+            );
+        }
+
+        Ok(Self {
+            command,
+            dialect,
+            width,
+            opt_level,
+            enabled_passes,
+            disabled_passes,
+            pass_stats,
+            tape,
+            max_tape_len,
+            eof,
+            cells,
+            checkpoint_every,
+            resume,
+        })
+    }
+
+    /// 最適化レベル，`--enable-pass`，`--disable-pass`とセルとテープの設定に従う最適化器
+    pub fn optimizer(&self) -> Optimizer {
+        // REPLは前の行のテープを引き継ぐので，開始時のセルが0とは限らない
+        let mut optimizer = Optimizer::with_level(self.opt_level)
+            .with_cells(self.cells)
+            .with_zeroed_entry(self.command != Command::Repl)
+            .with_tape(self.tape)
+            .with_max_tape_len(self.max_tape_len);
+        for name in self.enabled_passes.iter() {
+            if !optimizer.pass_names().contains(&name.as_str()) {
+                optimizer = optimizer.with_pass(named_pass(name).unwrap());
+            }
+        }
+        for name in self.disabled_passes.iter() {
+            optimizer = optimizer.without_pass(name);
+        }
+
+        optimizer
+    }
+}
+
+/// `--name value`または`--name=value`の形式のオプションであれば，その値を返す
+fn option_value(
+    name: &str,
+    arg: &str,
+    rest: &mut impl Iterator<Item = String>,
+) -> Result<Option<String>> {
+    let Some(suffix) = arg.strip_prefix(name) else {
+        return Ok(None);
+    };
+
+    if suffix.is_empty() {
+        rest.next()
+            .map(Some)
+            .ok_or_else(|| anyhow!("missing value for {}", name))
+    } else if let Some(value) = suffix.strip_prefix('=') {
+        Ok(Some(value.to_string()))
+    } else {
+        Ok(None)
+    }
+}
+
+fn check_pass_name(name: &str) -> Result<()> {
+    if named_pass(name).is_none() {
+        bail!("unknown pass: {}", name);
+    }
+    Ok(())
+}
+
+/// 組み込みの綴りの名前，または対応表のファイルから綴りを読み込む
+fn load_dialect(value: &str) -> Result<Dialect> {
+    if let Some(dialect) = Dialect::from_name(value) {
+        return Ok(dialect);
+    }
+
+    let table = std::fs::read_to_string(value)
+        .map_err(|e| anyhow!("failed to read dialect table {}: {}", value, e))?;
+    Dialect::from_table(&table).map_err(|e| anyhow!("invalid dialect table {}: {}", value, e))
+}
@@ -0,0 +1,604 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::io::{self, stdin, stdout, Read, Write};
+use std::str::FromStr;
+
+use ast::cell::CellConfig;
+use ast::inst::Op;
+use ast::source_map::SourceRange;
+use bytecode_backend::error::RuntimeError;
+use bytecode_backend::interpreter::Interpreter;
+use parser::token::Span;
+
+/// `help`で表示する説明
+const HELP: &str = "\
+break LINE[:COLUMN] | break #OP   stop before the op
+delete LINE[:COLUMN] | delete #OP remove a breakpoint
+watch CELL                        stop when the cell changes
+step [N]                          run N ops (default 1)
+next                              run a whole loop as one step
+continue                          run until a breakpoint or watchpoint
+back [N]                          undo N ops (default 1)
+reverse-continue                  run backwards to the previous breakpoint
+tape [RADIUS]                     print cells around the pointer
+set CELL VALUE                    write a value to a cell
+where                             print the next op
+quit                              exit the debugger";
+
+/// 命令ごとのソースコード上の位置
+#[derive(Debug, Clone, Default)]
+pub struct SourcePositions {
+    /// 命令ごとの元になったトークンの添字の範囲
+    ranges: Vec<SourceRange>,
+    /// トークンごとのソースコード上の範囲
+    spans: Vec<Span>,
+}
+
+impl SourcePositions {
+    pub fn new(ranges: Vec<SourceRange>, spans: Vec<Span>) -> Self {
+        Self { ranges, spans }
+    }
+
+    /// 命令の元になった最初のトークンの位置
+    pub fn position(&self, op: usize) -> Option<Span> {
+        let range = self.ranges.get(op).filter(|range| !range.is_empty())?;
+        self.spans.get(range.start).copied()
+    }
+
+    /// その位置のトークンから作った最初の命令．最適化で消えたトークンであれば，その後の最初の命令
+    pub fn find(&self, line: usize, column: Option<usize>) -> Option<usize> {
+        let token = self.spans.iter().position(|span| {
+            span.line == line
+                && column
+                    .is_none_or(|column| (span.column..span.column + span.len).contains(&column))
+        })?;
+
+        self.ranges
+            .iter()
+            .position(|range| (range.start..range.end).contains(&token))
+            .or_else(|| {
+                self.ranges
+                    .iter()
+                    .position(|range| !range.is_empty() && range.start > token)
+            })
+    }
+}
+
+/// ブレークポイントの位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    /// 命令の添字
+    Op(usize),
+    /// ソースコード上の行と列．列を省くとその行の最初の命令
+    Source { line: usize, column: Option<usize> },
+}
+
+impl FromStr for Location {
+    type Err = String;
+
+    /// `#OP`，`LINE`，`LINE:COLUMN`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = |s: &str| {
+            s.parse::<usize>()
+                .map_err(|_| format!("invalid location: {}", s))
+        };
+
+        if let Some(op) = s.strip_prefix('#') {
+            return Ok(Location::Op(number(op)?));
+        }
+        match s.split_once(':') {
+            Some((line, column)) => Ok(Location::Source {
+                line: number(line)?,
+                column: Some(number(column)?),
+            }),
+            None => Ok(Location::Source {
+                line: number(s)?,
+                column: None,
+            }),
+        }
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::Op(op) => write!(f, "#{}", op),
+            Location::Source { line, column: None } => write!(f, "{}", line),
+            Location::Source {
+                line,
+                column: Some(column),
+            } => write!(f, "{}:{}", line, column),
+        }
+    }
+}
+
+/// デバッガのコマンド
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugCommand {
+    Break(Location),
+    Delete(Location),
+    Watch(isize),
+    Step(usize),
+    /// ループの先頭であれば，ループを抜けるまで実行する
+    Next,
+    Continue,
+    /// 記録を使って命令を戻す
+    Back(usize),
+    /// 記録を使って前のブレークポイントまで戻る
+    ReverseContinue,
+    /// ポインタの前後に表示するセルの数
+    Tape(usize),
+    Set {
+        cell: isize,
+        value: u32,
+    },
+    Where,
+    Help,
+    Quit,
+}
+
+impl FromStr for DebugCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let name = words.next().ok_or("empty command")?;
+        let args: Vec<&str> = words.collect();
+
+        fn parse<T: FromStr>(arg: Option<&&str>, what: &str) -> Result<T, String> {
+            let arg = arg.ok_or_else(|| format!("missing {}", what))?;
+            arg.parse()
+                .map_err(|_| format!("invalid {}: {}", what, arg))
+        }
+
+        let command = match name {
+            "break" | "b" => DebugCommand::Break(parse(args.first(), "location")?),
+            "delete" | "d" => DebugCommand::Delete(parse(args.first(), "location")?),
+            "watch" | "w" => DebugCommand::Watch(parse(args.first(), "cell")?),
+            "step" | "s" => match args.first() {
+                Some(_) => DebugCommand::Step(parse(args.first(), "count")?),
+                None => DebugCommand::Step(1),
+            },
+            "next" | "n" => DebugCommand::Next,
+            "continue" | "c" => DebugCommand::Continue,
+            "back" | "bs" => match args.first() {
+                Some(_) => DebugCommand::Back(parse(args.first(), "count")?),
+                None => DebugCommand::Back(1),
+            },
+            "reverse-continue" | "rc" => DebugCommand::ReverseContinue,
+            "tape" | "t" => match args.first() {
+                Some(_) => DebugCommand::Tape(parse(args.first(), "radius")?),
+                None => DebugCommand::Tape(8),
+            },
+            "set" => DebugCommand::Set {
+                cell: parse(args.first(), "cell")?,
+                value: parse(args.get(1), "value")?,
+            },
+            "where" => DebugCommand::Where,
+            "help" | "h" => DebugCommand::Help,
+            "quit" | "q" => DebugCommand::Quit,
+            _ => return Err(format!("unknown command: {}", name)),
+        };
+        Ok(command)
+    }
+}
+
+/// 実行が止まった理由
+#[derive(Debug)]
+enum Stop {
+    /// 指示された分だけ実行した
+    Done,
+    Breakpoint,
+    Watch {
+        cell: isize,
+        old: u32,
+        new: u32,
+    },
+    Finished,
+    /// 記録の始めより前には戻れない
+    StartOfRecording,
+    Error(RuntimeError),
+}
+
+/// 命令を1つずつ実行するデバッガ
+pub struct Debugger<R: Read, W: Write> {
+    interpreter: Interpreter<R, W>,
+    positions: SourcePositions,
+    cells: CellConfig,
+    /// ブレークポイントを置いた命令の添字
+    breakpoints: BTreeSet<usize>,
+    /// 監視しているセルと，最後に見た値
+    watchpoints: BTreeMap<isize, u32>,
+}
+
+impl<R: Read, W: Write> Debugger<R, W> {
+    pub fn new(
+        interpreter: Interpreter<R, W>,
+        positions: SourcePositions,
+        cells: CellConfig,
+    ) -> Self {
+        Self {
+            interpreter,
+            positions,
+            cells,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+        }
+    }
+
+    /// コマンドを実行し，結果を`out`に書く．`quit`であれば`false`を返す
+    pub fn execute(&mut self, command: DebugCommand, out: &mut impl Write) -> io::Result<bool> {
+        match command {
+            DebugCommand::Break(location) => match self.resolve(location) {
+                Some(op) => {
+                    self.breakpoints.insert(op);
+                    write!(out, "breakpoint ")?;
+                    self.print_op(op, out)?;
+                }
+                None => writeln!(out, "no op at {}", location)?,
+            },
+            DebugCommand::Delete(location) => {
+                match self
+                    .resolve(location)
+                    .filter(|op| self.breakpoints.remove(op))
+                {
+                    Some(op) => writeln!(out, "deleted breakpoint #{}", op)?,
+                    None => writeln!(out, "no breakpoint at {}", location)?,
+                }
+            }
+            DebugCommand::Watch(cell) => {
+                self.watchpoints.insert(cell, self.cell(cell));
+                writeln!(out, "watching cell {}", cell)?;
+            }
+            DebugCommand::Step(count) => {
+                let mut stop = Stop::Done;
+                for _ in 0..count {
+                    stop = self.step();
+                    if !matches!(stop, Stop::Done) {
+                        break;
+                    }
+                }
+                self.print_stop(stop, out)?;
+            }
+            DebugCommand::Next => {
+                let ip = self.ip();
+                let stop = match self.interpreter.machine().code().vec().get(ip) {
+                    Some(&Op::LoopStart { if_zero_add }) => self.run(Some(ip + if_zero_add + 1)),
+                    _ => self.step(),
+                };
+                self.print_stop(stop, out)?;
+            }
+            DebugCommand::Continue => {
+                let stop = self.run(None);
+                self.print_stop(stop, out)?;
+            }
+            DebugCommand::Back(count) => {
+                let back = (0..count)
+                    .take_while(|_| self.interpreter.step_back())
+                    .count();
+                let stop = if back < count {
+                    Stop::StartOfRecording
+                } else {
+                    Stop::Done
+                };
+                self.forget_watched_values();
+                self.print_stop(stop, out)?;
+            }
+            DebugCommand::ReverseContinue => {
+                let breakpoints = &self.breakpoints;
+                let stop = if self.interpreter.run_back_to(|ip| breakpoints.contains(&ip)) {
+                    Stop::Breakpoint
+                } else {
+                    Stop::StartOfRecording
+                };
+                self.forget_watched_values();
+                self.print_stop(stop, out)?;
+            }
+            DebugCommand::Tape(radius) => self.print_tape(radius, out)?,
+            DebugCommand::Set { cell, value } => {
+                if value > self.cells.width.max() {
+                    writeln!(out, "value out of range: {}", value)?;
+                } else if let Some(target) = self.interpreter.tape_mut().cell_mut(cell) {
+                    *target = value;
+                    // 自分で書き換えた値では止まらない
+                    if let Some(watched) = self.watchpoints.get_mut(&cell) {
+                        *watched = value;
+                    }
+                } else {
+                    writeln!(out, "cell {} is not on the tape", cell)?;
+                }
+            }
+            DebugCommand::Where => self.print_stop(Stop::Done, out)?,
+            DebugCommand::Help => writeln!(out, "{}", HELP)?,
+            DebugCommand::Quit => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    fn ip(&self) -> usize {
+        self.interpreter.machine().ip()
+    }
+
+    fn cell(&self, position: isize) -> u32 {
+        // まだ使っていないセルは0
+        self.interpreter.tape().cell(position).unwrap_or(0)
+    }
+
+    /// 戻った後の値を監視しているセルの値にする．戻ったことでは止まらない
+    fn forget_watched_values(&mut self) {
+        for (&cell, value) in self.watchpoints.iter_mut() {
+            *value = self.interpreter.tape().cell(cell).unwrap_or(0);
+        }
+    }
+
+    /// ブレークポイントの位置を命令の添字にする
+    fn resolve(&self, location: Location) -> Option<usize> {
+        match location {
+            Location::Op(op) => (op < self.interpreter.machine().code().vec().len()).then_some(op),
+            Location::Source { line, column } => self.positions.find(line, column),
+        }
+    }
+
+    /// 命令を1つ実行する
+    fn step(&mut self) -> Stop {
+        if self.interpreter.is_finished() {
+            return Stop::Finished;
+        }
+        if let Err(e) = self.interpreter.step() {
+            return Stop::Error(e);
+        }
+
+        for (&cell, old) in self.watchpoints.iter_mut() {
+            let new = self.interpreter.tape().cell(cell).unwrap_or(0);
+            if new != *old {
+                let stop = Stop::Watch {
+                    cell,
+                    old: *old,
+                    new,
+                };
+                *old = new;
+                return stop;
+            }
+        }
+
+        if self.interpreter.is_finished() {
+            Stop::Finished
+        } else {
+            Stop::Done
+        }
+    }
+
+    /// ブレークポイントか監視しているセルの変化，または命令`until`に着くまで実行する
+    fn run(&mut self, until: Option<usize>) -> Stop {
+        loop {
+            let stop = self.step();
+            if !matches!(stop, Stop::Done) {
+                return stop;
+            }
+            if until == Some(self.ip()) {
+                return Stop::Done;
+            }
+            if self.breakpoints.contains(&self.ip()) {
+                return Stop::Breakpoint;
+            }
+        }
+    }
+
+    fn print_stop(&self, stop: Stop, out: &mut impl Write) -> io::Result<()> {
+        match stop {
+            Stop::Done => {}
+            Stop::Breakpoint => write!(out, "breakpoint: ")?,
+            Stop::Watch { cell, old, new } => writeln!(out, "cell {}: {} -> {}", cell, old, new)?,
+            Stop::Finished => return writeln!(out, "program finished"),
+            Stop::StartOfRecording => write!(out, "start of recording: ")?,
+            Stop::Error(e) => writeln!(out, "{}", e)?,
+        }
+
+        if self.interpreter.is_finished() {
+            writeln!(out, "program finished")
+        } else {
+            self.print_op(self.ip(), out)
+        }
+    }
+
+    /// 命令とソースコード上の位置
+    fn print_op(&self, op: usize, out: &mut impl Write) -> io::Result<()> {
+        let code = self.interpreter.machine().code().vec();
+        match self.positions.position(op) {
+            Some(span) => writeln!(
+                out,
+                "#{} at {}:{}: {:?}",
+                op, span.line, span.column, code[op]
+            ),
+            None => writeln!(out, "#{}: {:?}", op, code[op]),
+        }
+    }
+
+    fn print_tape(&self, radius: usize, out: &mut impl Write) -> io::Result<()> {
+        let tape = self.interpreter.tape();
+        let pointer = tape.position();
+        let radius = radius as isize;
+        for position in pointer - radius..=pointer + radius {
+            let Some(value) = tape.cell(position) else {
+                continue;
+            };
+            let marker = if position == pointer { '>' } else { ' ' };
+            writeln!(out, "{} {:6}: {}", marker, position, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// `,`を実行する時に標準入力から1行読む
+///
+/// デバッガのコマンドも標準入力から読むので，必要になるまで読まない
+#[derive(Debug, Default)]
+pub struct LineInput {
+    /// 読んだ行の残り
+    pending: Vec<u8>,
+}
+
+impl Read for LineInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            print!("input> ");
+            stdout().flush()?;
+            let mut line = String::new();
+            stdin().read_line(&mut line)?;
+            self.pending = line.into_bytes();
+        }
+
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::inst::OpCode;
+    use ast::opt::Optimizer;
+    use bytecode_backend::history::Recording;
+    use parser::parser::Parser;
+    use parser::scanner::Scanner;
+    use parser::token::Token;
+
+    fn debugger(
+        source: &str,
+        input: &'static [u8],
+        optimizer: Option<Optimizer>,
+    ) -> Debugger<&'static [u8], Vec<u8>> {
+        let tokens = Scanner::new(source.as_bytes()).scan_tokens();
+        let spans = tokens.iter().map(Token::span).collect();
+        let (code, map) = Parser::new(tokens).parse_tokens_mapped().unwrap();
+        let (code, map) = match optimizer {
+            Some(optimizer) => {
+                let (code, map, _) = optimizer.optimize_mapped(code, map);
+                (code, map)
+            }
+            None => (code, map),
+        };
+        let (code, ranges) = OpCode::from_mapped(code, map);
+        let interpreter =
+            Interpreter::new(code, input, Vec::new()).with_recording(Recording::default());
+        Debugger::new(
+            interpreter,
+            SourcePositions::new(ranges, spans),
+            CellConfig::default(),
+        )
+    }
+
+    fn execute(debugger: &mut Debugger<&'static [u8], Vec<u8>>, command: &str) -> String {
+        let mut out = Vec::new();
+        debugger
+            .execute(command.parse().unwrap(), &mut out)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn stops_at_breakpoints_and_watchpoints() {
+        let mut debugger = debugger("+++\n>,<[->+<]", b"a", None);
+
+        assert_eq!(
+            execute(&mut debugger, "break 2:4"),
+            "breakpoint #4 at 2:4: [ (3)\n"
+        );
+        assert_eq!(
+            execute(&mut debugger, "continue"),
+            "breakpoint: #4 at 2:4: [ (3)\n"
+        );
+        assert_eq!(debugger.interpreter.memory()[1], b'a' as u32);
+
+        execute(&mut debugger, "watch 1");
+        assert_eq!(
+            execute(&mut debugger, "c"),
+            "cell 1: 97 -> 98\n#7 at 2:9: ] (3)\n"
+        );
+
+        assert_eq!(
+            execute(&mut debugger, "delete #4"),
+            "deleted breakpoint #4\n"
+        );
+        execute(&mut debugger, "set 1 0");
+        assert_eq!(
+            execute(&mut debugger, "tape 1"),
+            ">      0: 2\n       1: 0\n"
+        );
+    }
+
+    #[test]
+    fn next_steps_over_loops() {
+        let mut debugger = debugger("++[-]>+", b"", None);
+
+        execute(&mut debugger, "step 2");
+        assert_eq!(execute(&mut debugger, "next"), "#5 at 1:7: + (1) @1\n");
+        assert_eq!(debugger.interpreter.memory()[0], 0);
+        assert_eq!(execute(&mut debugger, "s 5"), "program finished\n");
+    }
+
+    #[test]
+    fn breakpoints_in_optimized_code() {
+        let mut debugger = debugger("+[->+<]\n>.", b"", Some(Optimizer::new()));
+
+        // ループ全体が1つの命令になる
+        assert_eq!(
+            execute(&mut debugger, "break 1:4"),
+            "breakpoint #1 at 1:2: SumRight(1)\n"
+        );
+        assert_eq!(
+            execute(&mut debugger, "break 2"),
+            "breakpoint #3 at 2:1: > (1)\n"
+        );
+    }
+
+    #[test]
+    fn runs_backwards() {
+        let mut debugger = debugger("+++\n>,<[->+<]", b"a", None);
+
+        execute(&mut debugger, "break #4");
+        execute(&mut debugger, "continue");
+        execute(&mut debugger, "step 4");
+        execute(&mut debugger, "watch 1");
+        assert_eq!(debugger.interpreter.memory()[..2], [2, 98]);
+
+        // 戻った分の変化では止まらない
+        assert_eq!(
+            execute(&mut debugger, "reverse-continue"),
+            "breakpoint: #4 at 2:4: [ (3)\n"
+        );
+        assert_eq!(debugger.interpreter.memory()[..2], [3, 97]);
+        assert_eq!(execute(&mut debugger, "back"), "#3 at 2:2: Input @1\n");
+        assert_eq!(
+            execute(&mut debugger, "rc"),
+            "start of recording: #0 at 1:1: + (1) @0\n"
+        );
+
+        // 戻した入力をもう一度読む
+        assert_eq!(
+            execute(&mut debugger, "c"),
+            "cell 1: 0 -> 97\n#4 at 2:4: [ (3)\n"
+        );
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(
+            "b 3".parse(),
+            Ok(DebugCommand::Break(Location::Source {
+                line: 3,
+                column: None
+            }))
+        );
+        assert_eq!(
+            "set -2 7".parse(),
+            Ok(DebugCommand::Set { cell: -2, value: 7 })
+        );
+        assert_eq!("bs 3".parse(), Ok(DebugCommand::Back(3)));
+        assert!("step x".parse::<DebugCommand>().is_err());
+        assert!("jump".parse::<DebugCommand>().is_err());
+    }
+}
@@ -1,37 +1,95 @@
+mod args;
+mod debug;
+
 use std::env;
 use std::fs::File;
-use std::io::{stdin, stdout, Read, Write};
+use std::io::{stdin, stdout, BufReader, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
 
 use anyhow::{anyhow, Result};
-use ast::inst::OpCode;
+use args::{Args, Command};
+use ast::emit::emit_with_cells;
+use ast::inst::{AstCode, OpCode};
+use ast::source_map::SourceMap;
+use bytecode_backend::budget::{Budget, Outcome};
+use bytecode_backend::checkpoint::Checkpoint;
+use bytecode_backend::history::Recording;
+use bytecode_backend::interpreter::Interpreter;
+use debug::{DebugCommand, Debugger, LineInput, SourcePositions};
 use inkwell::context::Context;
 use inkwell::targets::{self, CodeModel, RelocMode, Target, TargetMachine};
 use inkwell::OptimizationLevel;
+use parser::diagnostic;
+use parser::dialect::Dialect;
+use parser::formatter::Formatter;
 use parser::parser::Parser;
 use parser::scanner::Scanner;
+use parser::token::{Span, Token};
 
 fn main() {
-    let Some(file_name) = env::args().nth(1) else {
-        repl();
-        return;
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let file_name = match &args.command {
+        Command::Repl => {
+            repl(&args);
+            return;
+        }
+        Command::Fmt(file_name) => {
+            if let Err(e) = fmt(file_name, &args) {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Command::Minify(file_name) => {
+            if let Err(e) = minify(file_name, &args) {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Command::Debug(file_name) => {
+            if let Err(e) = debug(file_name, &args) {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Command::Compile(file_name) => file_name,
     };
     let mut file = File::open(file_name).expect("failed to open file");
     let mut src = String::new();
     file.read_to_string(&mut src).expect("failed to read file");
 
-    let mut scanner = Scanner::new(src.chars().collect());
-    let tokens = scanner.scan_tokens();
+    let scanner = Scanner::with_dialect(src.as_bytes(), &args.dialect);
 
-    let parser = Parser::new(tokens);
-    let parse_result = parser.parse_tokens();
-    let Ok(program) = parse_result else {
-        for error in parse_result.unwrap_err() {
-            eprintln!("{}", error);
+    let parser = Parser::new(scanner);
+    let parse_result = parser.parse_tokens_mapped();
+    let (program, map) = match parse_result {
+        Ok(program) => program,
+        Err(errors) => {
+            eprint!("{}", diagnostic::render_all(&src, &errors));
+            std::process::exit(1);
         }
-        panic!("failed to parse tokens");
     };
 
+    let (program, _) = optimize(program, map, &args);
+
+    // 状態を保存できるのはインタプリタだけなので，LLVMを使わずに実行する
+    if args.checkpoint_every.is_some() || args.resume.is_some() {
+        if let Err(e) = interpret(program, file_name, &args) {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // let compiler = vm::compiler::Compiler::new();
     // let code = compiler.compile(program);
     // let mut interpreter = Interpreter::new(code, stdin(), stdout());
@@ -40,7 +98,9 @@ fn main() {
     let context = Context::create();
     let machine = host_machine().expect("failed to create machine");
 
-    let mut compiler = llvm_backend::compiler::Compiler::new(&context, machine);
+    let mut compiler = llvm_backend::compiler::Compiler::new(&context, machine)
+        .with_cells(args.cells)
+        .with_eof(args.eof);
     compiler.compile(program);
     compiler.write_to_file(Path::new("a.o")).unwrap();
 
@@ -49,29 +109,207 @@ fn main() {
     compiler.run_jit().unwrap();
 }
 
-fn repl() {
-    let mut interpreter =
-        bytecode_backend::interpreter::Interpreter::new(OpCode::default(), stdin(), stdout());
+/// ファイルを整形して標準出力に書き出す
+fn fmt(file_name: &Path, args: &Args) -> Result<()> {
+    if args.dialect != Dialect::brainfuck() {
+        anyhow::bail!("fmt supports only brainfuck sources");
+    }
+
+    let src = std::fs::read_to_string(file_name)?;
+
+    let mut formatter = Formatter::new();
+    if let Some(width) = args.width {
+        formatter.width = width;
+    }
+
+    match formatter.format(&src) {
+        Ok(formatted) => {
+            stdout().write_all(formatted.as_bytes())?;
+            Ok(())
+        }
+        Err(errors) => {
+            eprint!("{}", diagnostic::render_all(&src, &errors));
+            std::process::exit(1);
+        }
+    }
+}
+
+/// コメントを取り除き，打ち消し合う命令をまとめたBrainfuckを標準出力に書き出す
+fn minify(file_name: &Path, args: &Args) -> Result<()> {
+    let src = std::fs::read_to_string(file_name)?;
+
+    let scanner = Scanner::with_dialect(src.as_bytes(), &args.dialect);
+    match Parser::new(scanner).parse_tokens() {
+        Ok(program) => {
+            writeln!(stdout(), "{}", emit_with_cells(&program, args.cells))?;
+            Ok(())
+        }
+        Err(errors) => {
+            eprint!("{}", diagnostic::render_all(&src, &errors));
+            std::process::exit(1);
+        }
+    }
+}
+
+/// ファイルを1命令ずつ実行するデバッガを起動する．`-O0`で命令とトークンがほぼ1対1になる
+fn debug(file_name: &Path, args: &Args) -> Result<()> {
+    let src = std::fs::read_to_string(file_name)?;
+
+    let tokens = Scanner::with_dialect(src.as_bytes(), &args.dialect).scan_tokens();
+    let spans: Vec<Span> = tokens.iter().map(Token::span).collect();
+    let (program, map) = match Parser::new(tokens).parse_tokens_mapped() {
+        Ok(program) => program,
+        Err(errors) => {
+            eprint!("{}", diagnostic::render_all(&src, &errors));
+            std::process::exit(1);
+        }
+    };
+
+    let (program, map) = optimize(program, map, args);
+    let (code, ranges) = OpCode::from_mapped(program, map);
+    let interpreter = Interpreter::new(code, LineInput::default(), stdout())
+        .with_tape(args.tape)
+        .with_max_tape_len(args.max_tape_len)
+        .with_cells(args.cells)
+        .with_eof(args.eof)
+        .with_recording(Recording::default());
+    let mut debugger = Debugger::new(interpreter, SourcePositions::new(ranges, spans), args.cells);
+
+    let mut last = None;
+    loop {
+        print!("(bf) ");
+        stdout().flush()?;
+        let mut line = String::new();
+        if stdin().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        // 空行は直前のコマンドを繰り返す
+        let command = match line.trim() {
+            "" => match last {
+                Some(command) => command,
+                None => continue,
+            },
+            line => match line.parse::<DebugCommand>() {
+                Ok(command) => command,
+                Err(e) => {
+                    println!("{}", e);
+                    continue;
+                }
+            },
+        };
+        last = Some(command);
+
+        if !debugger.execute(command, &mut stdout())? {
+            return Ok(());
+        }
+    }
+}
+
+/// インタプリタで実行する．`--resume`があれば保存した状態から続け，
+/// `--checkpoint-every`があればその命令数ごとに状態を保存する
+///
+/// 保存先は`--resume`のファイル，なければ`file_name`の拡張子を`checkpoint`にしたファイル
+fn interpret(program: AstCode, file_name: &Path, args: &Args) -> Result<()> {
+    let mut interpreter = Interpreter::new(OpCode::from(program), stdin(), stdout())
+        .with_tape(args.tape)
+        .with_max_tape_len(args.max_tape_len)
+        .with_cells(args.cells)
+        .with_eof(args.eof);
+
+    if let Some(resume) = &args.resume {
+        let file = File::open(resume)
+            .map_err(|e| anyhow!("failed to open {}: {}", resume.display(), e))?;
+        let checkpoint = Checkpoint::read_from(BufReader::new(file))
+            .map_err(|e| anyhow!("failed to read {}: {}", resume.display(), e))?;
+        interpreter
+            .restore(checkpoint)
+            .map_err(|e| anyhow!("failed to resume from {}: {}", resume.display(), e))?;
+    }
+
+    let Some(every) = args.checkpoint_every else {
+        interpreter.run()?;
+        return Ok(());
+    };
+    let path = match &args.resume {
+        Some(resume) => resume.clone(),
+        None => file_name.with_extension("checkpoint"),
+    };
+    loop {
+        *interpreter.budget_mut() = Budget::unlimited().with_steps(every);
+        if interpreter.run()? == Outcome::Finished {
+            return Ok(());
+        }
+        save_checkpoint(&interpreter.checkpoint()?, &path)?;
+    }
+}
+
+/// 書き込み中に止まっても前の状態が残るように，別のファイルに書いてから置き換える
+fn save_checkpoint(checkpoint: &Checkpoint, path: &Path) -> Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+
+    let mut file = BufWriter::new(File::create(&temporary)?);
+    checkpoint.write_to(&mut file)?;
+    file.into_inner()?.sync_all()?;
+    std::fs::rename(&temporary, path)
+        .map_err(|e| anyhow!("failed to write {}: {}", path.display(), e))
+}
+
+/// `args`の最適化レベルで最適化し，`map`を合わせる．`--pass-stats`があればパスごとのノード数を書き出す
+fn optimize(program: AstCode, map: SourceMap, args: &Args) -> (AstCode, SourceMap) {
+    let (program, map, stats) = args.optimizer().optimize_mapped(program, map);
+    if args.pass_stats {
+        for stats in stats {
+            eprintln!("{}", stats);
+        }
+    }
+
+    (program, map)
+}
+
+fn repl(args: &Args) {
+    let mut interpreter = Interpreter::new(OpCode::default(), stdin(), stdout())
+        .with_tape(args.tape)
+        .with_max_tape_len(args.max_tape_len)
+        .with_cells(args.cells)
+        .with_eof(args.eof);
     loop {
         print!("> ");
         stdout().flush().unwrap();
         let mut src = String::new();
         stdin().read_line(&mut src).unwrap();
-        let mut scanner = Scanner::new(src.chars().collect());
-        let tokens = scanner.scan_tokens();
-        let parser = Parser::new(tokens);
-        let parse_result = parser.parse_tokens();
-        let Ok(program) = parse_result else {
-            for error in parse_result.unwrap_err() {
-                eprintln!("{}", error);
-            }
-            continue;
-        };
+        run_line(&mut interpreter, &src, args);
+    }
+}
+
+/// REPLの1行を実行する．テープは前の行から引き継ぐ
+fn run_line<R: Read, W: Write>(interpreter: &mut Interpreter<R, W>, src: &str, args: &Args) {
+    let scanner = Scanner::with_dialect(src.as_bytes(), &args.dialect);
+    let parser = Parser::new(scanner);
+    let parse_result = parser.parse_tokens_mapped();
+    let (program, map) = match parse_result {
+        Ok(program) => program,
+        Err(errors) => {
+            eprint!("{}", diagnostic::render_all(src, &errors));
+            return;
+        }
+    };
 
-        let code: OpCode = program.into();
-        interpreter.update(code);
+    let (program, map) = optimize(program, map, args);
+    let (code, ranges) = OpCode::from_mapped(program, map);
+    interpreter.update(code);
 
-        interpreter.run();
+    if let Err(e) = interpreter.run() {
+        // 実行時エラーは，元になった命令の位置を付けて書き出す
+        let spans: Vec<Span> = Scanner::with_dialect(src.as_bytes(), &args.dialect)
+            .map(|token| token.span())
+            .collect();
+        match SourcePositions::new(ranges, spans).position(e.ip) {
+            Some(span) => eprintln!("{}:{}: {}", span.line, span.column, e),
+            None => eprintln!("{}", e),
+        }
     }
 }
 
@@ -123,3 +361,56 @@ fn link(object: &Path) -> anyhow::Result<PathBuf> {
 
     Ok(output)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `args`で起動した時と同じく最適化して，`lines`を1つのインタプリタで順に実行する
+    fn output(args: &[&str], lines: &[&str]) -> Vec<u8> {
+        let args = Args::parse(args.iter().map(|arg| arg.to_string())).unwrap();
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::new(OpCode::default(), &b""[..], &mut output)
+            .with_tape(args.tape)
+            .with_max_tape_len(args.max_tape_len)
+            .with_cells(args.cells)
+            .with_eof(args.eof);
+        for line in lines {
+            run_line(&mut interpreter, line, &args);
+        }
+        drop(interpreter);
+        output
+    }
+
+    #[test]
+    fn repl_keeps_tape_between_lines() {
+        for level in ["-O0", "-O2", "-O3"] {
+            assert_eq!(output(&[level], &["+++\n", "[.-]\n"]), [3, 2, 1]);
+        }
+    }
+
+    #[test]
+    fn optimizes_for_circular_tape() {
+        for level in ["-O0", "-O1", "-O2", "-O3"] {
+            let args = [level, "--tape", "circular:8", "debug", "main.bf"];
+            assert_eq!(output(&args, &["+>>>>>>>>[.-]"]), [1]);
+        }
+    }
+
+    #[test]
+    fn rejects_tape_for_native_compile() {
+        let parse = |args: &[&str]| Args::parse(args.iter().map(|arg| arg.to_string()));
+        assert!(parse(&["--tape", "circular:8", "main.bf"]).is_err());
+        assert!(parse(&["--tape", "fixed", "main.bf"]).is_ok());
+        assert!(parse(&[
+            "--tape",
+            "unbounded",
+            "--checkpoint-every",
+            "100",
+            "main.bf"
+        ])
+        .is_ok());
+        assert!(parse(&["--tape", "unbounded", "debug", "main.bf"]).is_ok());
+        assert!(parse(&["--tape", "unbounded"]).is_ok());
+    }
+}
@@ -0,0 +1,142 @@
+use std::fmt::Display;
+
+use ast::inst::{AstCode, OpCode};
+use ast::opt::Optimizer;
+use bytecode_backend::interpreter::Interpreter;
+use parser::{parser::Parser, scanner::Scanner};
+
+/// 最適化なしで実行する命令の数の上限．これを超えるプログラムは比べない
+pub const STEP_LIMIT: usize = 10_000;
+
+/// 実行し終えた時の状態
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub output: Vec<u8>,
+    pub memory: Vec<u32>,
+    pub pointer: usize,
+}
+
+/// 最適化の有無で結果が変わった
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub program: String,
+    pub expected: Outcome,
+    /// 最適化した方が上限までに終わらないか，実行時エラーになった場合は`None`
+    pub actual: Option<Outcome>,
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "program: {}", self.program)?;
+        writeln!(
+            f,
+            "expected: output {:?}, pointer {}",
+            self.expected.output, self.expected.pointer
+        )?;
+        match &self.actual {
+            Some(actual) => {
+                write!(
+                    f,
+                    "actual:   output {:?}, pointer {}",
+                    actual.output, actual.pointer
+                )?;
+                if let Some(index) = (0..self.expected.memory.len())
+                    .find(|&i| self.expected.memory[i] != actual.memory[i])
+                {
+                    write!(
+                        f,
+                        ", cell {} is {} instead of {}",
+                        index, actual.memory[index], self.expected.memory[index]
+                    )?;
+                }
+                Ok(())
+            }
+            None => write!(f, "actual:   did not finish"),
+        }
+    }
+}
+
+/// 最適化しない場合と`optimizer`で最適化した場合の出力，テープ，ポインタを比べる
+///
+/// 構文解析できないか，最適化しない方が上限までに終わらないプログラムは比べずに`Ok`を返す
+pub fn check(program: &str, input: &[u8], optimizer: &Optimizer) -> Result<(), Box<Mismatch>> {
+    let Ok(code) = Parser::new(Scanner::new(program.as_bytes())).parse_tokens() else {
+        return Ok(());
+    };
+
+    let Some(expected) = execute(code.clone(), input) else {
+        return Ok(());
+    };
+    let actual = execute(optimizer.optimize(code), input);
+
+    if actual.as_ref() == Some(&expected) {
+        Ok(())
+    } else {
+        Err(Box::new(Mismatch {
+            program: program.to_string(),
+            expected,
+            actual,
+        }))
+    }
+}
+
+fn execute(code: AstCode, input: &[u8]) -> Option<Outcome> {
+    let code: OpCode = code.into();
+    let mut interpreter = Interpreter::new(code, input, Vec::new());
+    for _ in 0..STEP_LIMIT {
+        if interpreter.is_finished() {
+            return Some(Outcome {
+                output: interpreter.writer().get_ref().clone(),
+                memory: interpreter.memory().to_vec(),
+                pointer: interpreter.pointer(),
+            });
+        }
+        interpreter.step().ok()?;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_runs_wrap_modulo_256() {
+        let program = format!("{}.>{}.", "+".repeat(255), "-".repeat(257));
+        assert_eq!(check(&program, b"", &Optimizer::new()), Ok(()));
+    }
+
+    #[test]
+    fn partial_eval_keeps_final_tape() {
+        let optimizer = Optimizer::with_level(ast::opt::OptLevel::O3);
+        assert_eq!(check("-", b"", &optimizer), Ok(()));
+        assert_eq!(check("++>+++[-<+>]>-.", b"", &optimizer), Ok(()));
+    }
+
+    #[test]
+    fn reports_mismatch() {
+        // 最適化しない方が終わらないものは比べない
+        assert_eq!(check("+[]", b"", &Optimizer::new()), Ok(()));
+
+        let broken = Optimizer::new().with_pass(Box::new(DropOutput));
+        let mismatch = check("+.", b"", &broken).unwrap_err();
+        assert_eq!(mismatch.expected.output, vec![1]);
+        assert_eq!(mismatch.actual.unwrap().output, vec![]);
+    }
+
+    /// 出力を消してしまう壊れたパス
+    #[derive(Debug)]
+    struct DropOutput;
+
+    impl ast::opt::Pass for DropOutput {
+        fn name(&self) -> &'static str {
+            "drop-output"
+        }
+
+        fn run(&self, code: AstCode, _target: &ast::opt::Target) -> AstCode {
+            let mut code = code;
+            code.vec_mut().retain(|ast| *ast != ast::inst::Ast::Output);
+            code
+        }
+    }
+}
@@ -0,0 +1,156 @@
+/// 再現できるように種から決まる乱数列（xorshift64*）
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // 0の状態からは抜け出せないので避ける
+        Self(seed ^ 0x9e37_79b9_7f4a_7c15)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// `0..n`の一様な乱数
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// 確率`1 / n`で`true`
+    pub fn one_in(&mut self, n: usize) -> bool {
+        self.below(n) == 0
+    }
+}
+
+/// 括弧の対応が取れたランダムなプログラムを作る
+///
+/// ポインタが0より左に行かないように，`<`はそれまでの移動で確実に右にいる分だけ使う．
+/// 左方向のスキャンループは作らない
+#[derive(Debug, Clone)]
+pub struct Generator {
+    pub rng: Rng,
+    /// 1つのブロックの命令の数の上限
+    pub block_len: usize,
+    /// ループのネストの深さの上限
+    pub max_depth: usize,
+}
+
+impl Generator {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng::new(seed),
+            block_len: 12,
+            max_depth: 3,
+        }
+    }
+
+    pub fn program(&mut self) -> String {
+        let mut program = String::new();
+        let mut position = 0;
+        self.block(&mut program, &mut position, 0);
+        program
+    }
+
+    pub fn input(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.rng.next_u64() as u8).collect()
+    }
+
+    /// `position`はポインタの位置の下限
+    fn block(&mut self, program: &mut String, position: &mut usize, depth: usize) {
+        let len = self.rng.below(self.block_len) + 1;
+        for _ in 0..len {
+            match self.rng.below(12) {
+                0..=2 => {
+                    let command = if self.rng.one_in(2) { '+' } else { '-' };
+                    // 折り返しを試すために，時々長い並びを作る
+                    let count = if self.rng.one_in(8) {
+                        250 + self.rng.below(10)
+                    } else {
+                        self.rng.below(4) + 1
+                    };
+                    push_repeat(program, command, count);
+                }
+                3 | 4 => {
+                    let count = self.rng.below(3) + 1;
+                    push_repeat(program, '>', count);
+                    *position += count;
+                }
+                5 if *position > 0 => {
+                    let count = self.rng.below((*position).min(3)) + 1;
+                    push_repeat(program, '<', count);
+                    *position -= count;
+                }
+                6 => program.push('.'),
+                7 => program.push(','),
+                8 => {
+                    // [-]，[+]，[---]など
+                    let command = if self.rng.one_in(2) { '+' } else { '-' };
+                    program.push('[');
+                    push_repeat(program, command, self.rng.below(3) + 1);
+                    program.push(']');
+                }
+                9 => {
+                    // [>]，[>>]
+                    program.push('[');
+                    push_repeat(program, '>', self.rng.below(2) + 1);
+                    program.push(']');
+                }
+                10 | 11 if depth < self.max_depth => {
+                    // 本体の中で動いた分を戻して，ポインタが元の位置に戻るループにする
+                    let start = *position;
+                    program.push('[');
+                    if self.rng.one_in(2) {
+                        program.push('-');
+                    }
+                    self.block(program, position, depth + 1);
+                    if *position > start {
+                        push_repeat(program, '<', *position - start);
+                    } else {
+                        push_repeat(program, '>', start - *position);
+                    }
+                    *position = start;
+                    program.push(']');
+                }
+                _ => program.push('+'),
+            }
+        }
+    }
+}
+
+fn push_repeat(program: &mut String, command: char, count: usize) {
+    program.extend(std::iter::repeat_n(command, count));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_program() {
+        let programs: Vec<String> = (0..2).map(|_| Generator::new(42).program()).collect();
+        assert_eq!(programs[0], programs[1]);
+    }
+
+    #[test]
+    fn brackets_are_balanced() {
+        let mut generator = Generator::new(1);
+        for _ in 0..100 {
+            let program = generator.program();
+            let mut depth: usize = 0;
+            for c in program.chars() {
+                match c {
+                    '[' => depth += 1,
+                    ']' => depth = depth.checked_sub(1).unwrap(),
+                    _ => {}
+                }
+            }
+            assert_eq!(depth, 0, "{}", program);
+        }
+    }
+}
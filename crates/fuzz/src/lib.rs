@@ -0,0 +1,44 @@
+//! 最適化の前後でプログラムの動作が変わらないことを，ランダムなプログラムで確かめる
+
+pub mod diff;
+pub mod generator;
+pub mod minimize;
+
+use ast::opt::Optimizer;
+
+use diff::{check, Mismatch};
+use generator::Generator;
+use minimize::minimize;
+
+/// 種`seed`から`iterations`個のプログラムを作って比べる．
+/// 結果が変わったプログラムがあれば，小さくしたものを返す
+pub fn fuzz(seed: u64, iterations: usize, optimizer: &Optimizer) -> Result<(), Box<Mismatch>> {
+    let mut generator = Generator::new(seed);
+    for _ in 0..iterations {
+        let program = generator.program();
+        let input = generator.input(diff::STEP_LIMIT);
+
+        if check(&program, &input, optimizer).is_err() {
+            let program = minimize(&program, |p| check(p, &input, optimizer).is_err());
+            return check(&program, &input, optimizer);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ast::opt::OptLevel;
+
+    use super::*;
+
+    #[test]
+    fn optimizer_preserves_behavior() {
+        for level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::O3] {
+            if let Err(mismatch) = fuzz(0, 500, &Optimizer::with_level(level)) {
+                panic!("{:?}\n{}", level, mismatch);
+            }
+        }
+    }
+}
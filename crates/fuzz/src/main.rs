@@ -0,0 +1,28 @@
+use std::env;
+use std::process::exit;
+
+use ast::opt::{OptLevel, Optimizer};
+
+/// fuzz [回数] [種] [最適化レベル]
+fn main() {
+    let mut args = env::args().skip(1);
+    let iterations = args
+        .next()
+        .map_or(10_000, |s| s.parse().expect("invalid count"));
+    let seed = args.next().map_or(0, |s| s.parse().expect("invalid seed"));
+    let level: OptLevel = args
+        .next()
+        .map_or(Ok(OptLevel::default()), |s| s.parse())
+        .unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            exit(2);
+        });
+
+    match fuzz::fuzz(seed, iterations, &Optimizer::with_level(level)) {
+        Ok(()) => println!("{} programs passed", iterations),
+        Err(mismatch) => {
+            println!("{}", mismatch);
+            exit(1);
+        }
+    }
+}
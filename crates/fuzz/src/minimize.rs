@@ -0,0 +1,80 @@
+/// `fails`が`true`のままになるように，プログラムから命令を取り除いて小さくする
+///
+/// 1つの命令，ループ全体，ループの括弧だけを取り除くことを，どれも効かなくなるまで繰り返す．
+/// 括弧の対応は保たれる
+pub fn minimize(program: &str, mut fails: impl FnMut(&str) -> bool) -> String {
+    let mut program: Vec<u8> = program
+        .bytes()
+        .filter(|b| b"+-<>.,[]".contains(b))
+        .collect();
+
+    'outer: loop {
+        for i in 0..program.len() {
+            for candidate in candidates(&program, i) {
+                let candidate = String::from_utf8(candidate).unwrap();
+                if fails(&candidate) {
+                    program = candidate.into_bytes();
+                    continue 'outer;
+                }
+            }
+        }
+
+        return String::from_utf8(program).unwrap();
+    }
+}
+
+/// `i`番目の命令を取り除いたプログラムの候補
+fn candidates(program: &[u8], i: usize) -> Vec<Vec<u8>> {
+    let without = |indices: &[usize]| -> Vec<u8> {
+        program
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| !indices.contains(j))
+            .map(|(_, &b)| b)
+            .collect()
+    };
+
+    match program[i] {
+        b'[' => {
+            let end = matching_bracket(program, i);
+            let whole_loop = [&program[..i], &program[end + 1..]].concat();
+            vec![whole_loop, without(&[i, end])]
+        }
+        b']' => vec![],
+        _ => vec![without(&[i])],
+    }
+}
+
+fn matching_bracket(program: &[u8], start: usize) -> usize {
+    let mut depth = 0;
+    for (i, &b) in program.iter().enumerate().skip(start) {
+        match b {
+            b'[' => depth += 1,
+            b']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => {}
+        }
+    }
+    unreachable!("unbalanced brackets")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_irrelevant_commands() {
+        // 2つ以上の`+`の後に`.`があれば失敗する
+        let fails = |program: &str| {
+            program
+                .find('.')
+                .is_some_and(|i| program[..i].matches('+').count() >= 2)
+        };
+
+        assert_eq!(minimize("+>[-<+>]<+[,.]+. comment", fails), "++.");
+    }
+}
@@ -2,6 +2,7 @@ use std::io::Write;
 use std::path::Path;
 
 use anyhow::{anyhow, Ok, Result};
+use inkwell::basic_block::BasicBlock;
 use inkwell::builder::Builder;
 use inkwell::context::Context;
 use inkwell::execution_engine::ExecutionEngine;
@@ -11,8 +12,13 @@ use inkwell::types::{FunctionType, IntType, PointerType};
 use inkwell::values::{AnyValue, FunctionValue, GlobalValue, IntValue, PointerValue};
 use inkwell::{targets, AddressSpace, IntPredicate, OptimizationLevel};
 
+use ast::cell::{CellConfig, Overflow};
+use ast::eof::EofPolicy;
 use ast::inst::{Ast, AstCode};
 
+/// ループの開始ブロックと本体ブロック
+type LoopBlocks<'ctx> = (BasicBlock<'ctx>, BasicBlock<'ctx>);
+
 #[derive(Debug)]
 pub struct Compiler<'ctx> {
     context: &'ctx Context,
@@ -24,15 +30,22 @@ pub struct Compiler<'ctx> {
 
     types: Types<'ctx>,
     values: Values<'ctx>,
+
+    cells: CellConfig,
+    eof: EofPolicy,
 }
 
 #[derive(Debug)]
 struct Types<'ctx> {
     i8_ptr_type: PointerType<'ctx>,
-    i8_type: IntType<'ctx>,
+    /// セルの幅の整数型
+    cell_type: IntType<'ctx>,
     i32_type: IntType<'ctx>,
+    /// セルに加える値の型．32ビットのセルの値と64ビットの係数の積も溢れない
+    diff_type: IntType<'ctx>,
     getchar_fn_type: FunctionType<'ctx>,
     putchar_fn_type: FunctionType<'ctx>,
+    exit_fn_type: FunctionType<'ctx>,
     printf_fn_type: FunctionType<'ctx>,
     main_fn_type: FunctionType<'ctx>,
 }
@@ -42,6 +55,7 @@ struct Types<'ctx> {
 struct Values<'ctx> {
     getchar_fn: FunctionValue<'ctx>,
     putchar_fn: FunctionValue<'ctx>,
+    exit_fn: FunctionValue<'ctx>,
     printf_fn: FunctionValue<'ctx>,
     main_fn: FunctionValue<'ctx>,
 
@@ -60,12 +74,16 @@ impl<'ctx> Compiler<'ctx> {
 
         let types = Types {
             i8_ptr_type: context.i8_type().ptr_type(AddressSpace::default()),
-            i8_type: context.i8_type(),
+            cell_type: context.i8_type(),
             i32_type: context.i32_type(),
-            getchar_fn_type: context.i8_type().fn_type(&[], false),
+            diff_type: context.i128_type(),
+            getchar_fn_type: context.i32_type().fn_type(&[], false),
             putchar_fn_type: context
                 .i32_type()
-                .fn_type(&[context.i8_type().into()], false),
+                .fn_type(&[context.i32_type().into()], false),
+            exit_fn_type: context
+                .void_type()
+                .fn_type(&[context.i32_type().into()], false),
             printf_fn_type: context.i32_type().fn_type(
                 &[context.i8_type().ptr_type(AddressSpace::default()).into()],
                 true,
@@ -80,21 +98,14 @@ impl<'ctx> Compiler<'ctx> {
         // builderの位置をentry_blockに設定する。
         builder.position_at_end(entry_block);
 
-        let array_type = types.i8_type.array_type(30000);
-        let array_value = array_type.const_zero();
-
-        let array = builder.build_alloca(array_type, "array").unwrap();
-        builder.build_store(array, array_value).unwrap();
-
         let pointer_ptr = builder.build_alloca(types.i8_ptr_type, "pointer").unwrap();
 
-        builder.build_store(pointer_ptr, array).unwrap();
-
         let msg_ptr = builder.build_global_string_ptr("[%p]", "message").unwrap();
 
         let values = Values {
             getchar_fn: module.add_function("getchar", types.getchar_fn_type, None),
             putchar_fn: module.add_function("putchar", types.putchar_fn_type, None),
+            exit_fn: module.add_function("exit", types.exit_fn_type, None),
             printf_fn: module.add_function("printf", types.printf_fn_type, None),
             main_fn,
             msg_ptr,
@@ -109,16 +120,56 @@ impl<'ctx> Compiler<'ctx> {
             engine,
             types,
             values,
+            cells: CellConfig::default(),
+            eof: EofPolicy::default(),
         }
     }
 
+    /// セルの幅と折り返しを変える．`compile`の前に呼ぶ
+    pub fn with_cells(mut self, cells: CellConfig) -> Self {
+        self.cells = cells;
+        self.types.cell_type = self.context.custom_width_int_type(cells.width.bits());
+        self
+    }
+
+    /// 入力が終わった後の`,`の扱いを変える
+    pub fn with_eof(mut self, eof: EofPolicy) -> Self {
+        self.eof = eof;
+        self
+    }
+
     pub fn compile(&mut self, code: AstCode) {
-        for instruction in code.vec() {
-            self.compile_instruction(instruction);
+        // テープはセルの幅が決まってから作る
+        let array_type = self.types.cell_type.array_type(30000);
+        let array = self.builder.build_alloca(array_type, "array").unwrap();
+        self.builder
+            .build_store(array, array_type.const_zero())
+            .unwrap();
+        self.builder
+            .build_store(self.values.pointer_ptr, array)
+            .unwrap();
+
+        // 処理中のブロックの残りと，ループであればその開始ブロックと本体ブロック．
+        // 深くネストしたプログラムでもスタックを消費しないように，再帰は使わない
+        let mut stack: Vec<(std::slice::Iter<Ast>, Option<LoopBlocks<'ctx>>)> =
+            vec![(code.vec().iter(), None)];
+
+        while let Some((instructions, loop_blocks)) = stack.last_mut() {
+            match instructions.next() {
+                Some(Ast::Loop(body)) => {
+                    let loop_blocks = self.begin_loop();
+                    stack.push((body.vec().iter(), Some(loop_blocks)));
+                }
+                Some(instruction) => self.compile_instruction(instruction),
+                None => {
+                    if let Some((loop_start, loop_body)) = *loop_blocks {
+                        self.end_loop(loop_start, loop_body);
+                    }
+                    stack.pop();
+                }
+            }
         }
 
-        self.builder
-            .position_at_end(self.values.main_fn.get_last_basic_block().unwrap());
         self.builder
             .build_return(Some(&self.types.i32_type.const_int(0, false)))
             .unwrap();
@@ -132,7 +183,7 @@ impl<'ctx> Compiler<'ctx> {
                 let new_pointer = unsafe {
                     self.builder
                         .build_in_bounds_gep(
-                            self.types.i8_ptr_type,
+                            self.types.cell_type,
                             pointer,
                             &[self.types.i32_type.const_int(*count as u64, false)],
                             "incremented_pointer",
@@ -153,7 +204,7 @@ impl<'ctx> Compiler<'ctx> {
                 let new_pointer = unsafe {
                     self.builder
                         .build_in_bounds_gep(
-                            self.types.i8_ptr_type,
+                            self.types.cell_type,
                             pointer,
                             &[diff],
                             "decremented_pointer",
@@ -167,104 +218,292 @@ impl<'ctx> Compiler<'ctx> {
             }
             Ast::InclementValue(count) => {
                 let pointer = self.load_ptr(self.values.pointer_ptr);
-
-                let value = self.load_value(pointer);
-
-                let new_value = self
-                    .builder
-                    .build_int_add(
-                        value,
-                        self.types.i8_type.const_int(*count as u64, false),
-                        "incremented_value",
-                    )
-                    .unwrap();
-                self.builder.build_store(pointer, new_value).unwrap();
+                let diff = self.types.diff_type.const_int(*count as u64, false);
+                self.add_to_cell(pointer, diff);
             }
             Ast::DecrementValue(count) => {
                 let pointer = self.load_ptr(self.values.pointer_ptr);
-
-                let value = self.load_value(pointer);
-
-                let new_value = self
-                    .builder
-                    .build_int_sub(
-                        value,
-                        self.types.i8_type.const_int(*count as u64, false),
-                        "decremented_value",
-                    )
-                    .unwrap();
-                self.builder.build_store(pointer, new_value).unwrap();
+                let diff = self
+                    .types
+                    .diff_type
+                    .const_int(-(*count as i64) as u64, true);
+                self.add_to_cell(pointer, diff);
             }
             Ast::Output => {
                 let pointer = self.load_ptr(self.values.pointer_ptr);
 
+                // 8ビットより広いセルも，putcharが下位8ビットを出力する
                 let value = self.load_value(pointer);
+                let value = self
+                    .builder
+                    .build_int_z_extend_or_bit_cast(value, self.types.i32_type, "char")
+                    .unwrap();
                 self.builder
                     .build_call(self.values.putchar_fn, &[value.into()], "call_putchar")
                     .unwrap();
             }
-            Ast::Input => {
-                let value = self
-                    .builder
-                    .build_call(self.values.getchar_fn, &[], "call_getchar")
-                    .unwrap()
-                    .as_any_value_enum()
-                    .into_int_value();
+            Ast::Input => self.input(),
+            Ast::Load(n) => {
                 let pointer = self.load_ptr(self.values.pointer_ptr);
-                self.builder.build_store(pointer, value).unwrap();
+                self.builder
+                    .build_store(pointer, self.types.cell_type.const_int(*n as u64, false))
+                    .unwrap();
             }
-            Ast::Loop(instructions) => {
-                let loop_start = self
-                    .context
-                    .append_basic_block(self.values.main_fn, "loop_start");
-                let loop_body = self
-                    .context
-                    .append_basic_block(self.values.main_fn, "loop_body");
+            Ast::SumRight(count) => self.multiply_add(&[(*count as isize, 1)]),
+            Ast::SumLeft(count) => self.multiply_add(&[(-(*count as isize), 1)]),
+            Ast::MultiplyAdd(targets) => self.multiply_add(targets),
+            Ast::JumpZeroRight { per } => {
+                let (loop_start, loop_body) = self.begin_loop();
+                self.compile_instruction(&Ast::InclementPointer(*per));
+                self.end_loop(loop_start, loop_body);
+            }
+            Ast::JumpZeroLeft { per } => {
+                let (loop_start, loop_body) = self.begin_loop();
+                self.compile_instruction(&Ast::DecrementPointer(*per));
+                self.end_loop(loop_start, loop_body);
+            }
+            Ast::Loop(_) => unreachable!("loops are compiled by Compiler::compile"),
+        }
+    }
+
+    /// 1文字読み込んで現在のセルに書き込む．`getchar`がEOFを返した場合は`self.eof`に従う
+    fn input(&mut self) {
+        let value = self
+            .builder
+            .build_call(self.values.getchar_fn, &[], "call_getchar")
+            .unwrap()
+            .as_any_value_enum()
+            .into_int_value();
+        let pointer = self.load_ptr(self.values.pointer_ptr);
+
+        let read = self.context.append_basic_block(self.values.main_fn, "read");
+        let eof = self.context.append_basic_block(self.values.main_fn, "eof");
+        let input_end = self
+            .context
+            .append_basic_block(self.values.main_fn, "input_end");
+
+        let is_eof = self
+            .builder
+            .build_int_compare(
+                IntPredicate::EQ,
+                value,
+                self.types.i32_type.const_all_ones(),
+                "is_eof",
+            )
+            .unwrap();
+        self.builder
+            .build_conditional_branch(is_eof, eof, read)
+            .unwrap();
 
-                self.builder.build_unconditional_branch(loop_start).unwrap();
+        self.builder.position_at_end(read);
+        let byte = self
+            .builder
+            .build_int_truncate_or_bit_cast(value, self.types.cell_type, "byte")
+            .unwrap();
+        self.builder.build_store(pointer, byte).unwrap();
+        self.builder.build_unconditional_branch(input_end).unwrap();
 
-                self.builder.position_at_end(loop_body);
+        self.builder.position_at_end(eof);
+        match self.eof {
+            EofPolicy::Error => {
+                self.builder
+                    .build_call(
+                        self.values.exit_fn,
+                        &[self.types.i32_type.const_int(1, false).into()],
+                        "call_exit",
+                    )
+                    .unwrap();
+            }
+            EofPolicy::Unchanged => {}
+            EofPolicy::Zero => {
+                self.builder
+                    .build_store(pointer, self.types.cell_type.const_zero())
+                    .unwrap();
+            }
+            EofPolicy::MinusOne => {
+                self.builder
+                    .build_store(pointer, self.types.cell_type.const_all_ones())
+                    .unwrap();
+            }
+        }
+        self.builder.build_unconditional_branch(input_end).unwrap();
 
-                for instruction in instructions.vec() {
-                    self.compile_instruction(instruction);
-                }
+        self.builder.position_at_end(input_end);
+    }
 
-                let before_end = self.values.main_fn.get_last_basic_block().unwrap();
-                let loop_end = self
-                    .context
-                    .append_basic_block(self.values.main_fn, "loop_end");
+    /// 現在の値が0でなければ，係数を掛けて相対位置のセルに加え，現在のセルを0にする
+    fn multiply_add(&mut self, targets: &[(isize, isize)]) {
+        let pointer = self.load_ptr(self.values.pointer_ptr);
+        let value = self.load_value(pointer);
+
+        let multiply_add = self
+            .context
+            .append_basic_block(self.values.main_fn, "multiply_add");
+        let multiply_add_end = self
+            .context
+            .append_basic_block(self.values.main_fn, "multiply_add_end");
+
+        // ループの本体が実行されない場合は，移動先のセルに触れない
+        let condition = self
+            .builder
+            .build_int_compare(
+                IntPredicate::NE,
+                value,
+                self.types.cell_type.const_zero(),
+                "condition",
+            )
+            .unwrap();
+        self.builder
+            .build_conditional_branch(condition, multiply_add, multiply_add_end)
+            .unwrap();
 
-                self.builder.position_at_end(loop_start);
-                let pointer = self
-                    .builder
-                    .build_load(self.types.i8_ptr_type, self.values.pointer_ptr, "pointer")
+        self.builder.position_at_end(multiply_add);
+        for &(offset, factor) in targets {
+            let target = unsafe {
+                self.builder
+                    .build_in_bounds_gep(
+                        self.types.cell_type,
+                        pointer,
+                        &[self.types.i32_type.const_int(offset as u64, true)],
+                        "target_pointer",
+                    )
                     .unwrap()
-                    .into_pointer_value();
+            };
+            let wide_value = self
+                .builder
+                .build_int_z_extend(value, self.types.diff_type, "wide_value")
+                .unwrap();
+            let product = self
+                .builder
+                .build_int_mul(
+                    wide_value,
+                    self.types.diff_type.const_int(factor as u64, true),
+                    "product",
+                )
+                .unwrap();
+            self.add_to_cell(target, product);
+        }
+        self.builder
+            .build_store(pointer, self.types.cell_type.const_zero())
+            .unwrap();
+        self.builder
+            .build_unconditional_branch(multiply_add_end)
+            .unwrap();
 
-                let value = self.load_value(pointer);
+        self.builder.position_at_end(multiply_add_end);
+    }
 
-                let condition = self
+    /// セルに`diff_type`の`diff`を加える．折り返さない場合は，範囲外になるとプログラムを終了コード1で終える
+    fn add_to_cell(&mut self, pointer: PointerValue<'ctx>, diff: IntValue<'ctx>) {
+        let value = self.load_value(pointer);
+        let new_value = match self.cells.overflow {
+            Overflow::Wrapping => {
+                let diff = self
                     .builder
-                    .build_int_compare(
-                        IntPredicate::NE,
-                        value,
-                        self.types.i8_type.const_zero(),
-                        "condition",
-                    )
+                    .build_int_truncate_or_bit_cast(diff, self.types.cell_type, "diff")
                     .unwrap();
-
                 self.builder
-                    .build_conditional_branch(condition, loop_body, loop_end)
+                    .build_int_add(value, diff, "added_value")
+                    .unwrap()
+            }
+            Overflow::Checked => {
+                let wide_value = self
+                    .builder
+                    .build_int_z_extend(value, self.types.diff_type, "wide_value")
+                    .unwrap();
+                let sum = self.builder.build_int_add(wide_value, diff, "sum").unwrap();
+                // 負になった値も，符号なしで比べれば最大値より大きい
+                let max = self
+                    .types
+                    .diff_type
+                    .const_int(self.cells.width.max() as u64, false);
+                let in_range = self
+                    .builder
+                    .build_int_compare(IntPredicate::ULE, sum, max, "in_range")
                     .unwrap();
+                self.exit_unless(in_range);
+                self.builder
+                    .build_int_truncate(sum, self.types.cell_type, "added_value")
+                    .unwrap()
+            }
+        };
+        self.builder.build_store(pointer, new_value).unwrap();
+    }
 
-                self.builder.position_at_end(before_end);
+    /// `condition`が偽ならプログラムを終了コード1で終える
+    fn exit_unless(&mut self, condition: IntValue<'ctx>) {
+        let exit = self.context.append_basic_block(self.values.main_fn, "exit");
+        let checked = self
+            .context
+            .append_basic_block(self.values.main_fn, "checked");
+        self.builder
+            .build_conditional_branch(condition, checked, exit)
+            .unwrap();
 
-                self.builder.build_unconditional_branch(loop_start).unwrap();
+        self.builder.position_at_end(exit);
+        self.builder
+            .build_call(
+                self.values.exit_fn,
+                &[self.types.i32_type.const_int(1, false).into()],
+                "call_exit",
+            )
+            .unwrap();
+        self.builder.build_unreachable().unwrap();
 
-                self.builder.position_at_end(loop_end);
-            }
-            _ => todo!("todo: unsupported instruction: {:?}", instruction),
-        }
+        self.builder.position_at_end(checked);
+    }
+
+    /// ループの開始ブロックと本体ブロックを作り，本体ブロックの先頭に移動する
+    fn begin_loop(&mut self) -> LoopBlocks<'ctx> {
+        let loop_start = self
+            .context
+            .append_basic_block(self.values.main_fn, "loop_start");
+        let loop_body = self
+            .context
+            .append_basic_block(self.values.main_fn, "loop_body");
+
+        self.builder.build_unconditional_branch(loop_start).unwrap();
+
+        self.builder.position_at_end(loop_body);
+
+        (loop_start, loop_body)
+    }
+
+    /// ループの条件分岐を組み立て，ループの終了ブロックに移動する
+    fn end_loop(&mut self, loop_start: BasicBlock<'ctx>, loop_body: BasicBlock<'ctx>) {
+        let before_end = self.builder.get_insert_block().unwrap();
+        let loop_end = self
+            .context
+            .append_basic_block(self.values.main_fn, "loop_end");
+
+        self.builder.position_at_end(loop_start);
+        let pointer = self
+            .builder
+            .build_load(self.types.i8_ptr_type, self.values.pointer_ptr, "pointer")
+            .unwrap()
+            .into_pointer_value();
+
+        let value = self.load_value(pointer);
+
+        let condition = self
+            .builder
+            .build_int_compare(
+                IntPredicate::NE,
+                value,
+                self.types.cell_type.const_zero(),
+                "condition",
+            )
+            .unwrap();
+
+        self.builder
+            .build_conditional_branch(condition, loop_body, loop_end)
+            .unwrap();
+
+        self.builder.position_at_end(before_end);
+
+        self.builder.build_unconditional_branch(loop_start).unwrap();
+
+        self.builder.position_at_end(loop_end);
     }
 
     /// i8 ptr ptr -> i8 ptr
@@ -278,7 +517,7 @@ impl<'ctx> Compiler<'ctx> {
     /// i8 ptr -> i8
     fn load_value(&self, ptr: PointerValue<'ctx>) -> IntValue<'ctx> {
         self.builder
-            .build_load(self.types.i8_type, ptr, "value")
+            .build_load(self.types.cell_type, ptr, "value")
             .unwrap()
             .into_int_value()
     }
@@ -340,3 +579,87 @@ pub fn host_machine() -> Result<targets::TargetMachine> {
         )
         .ok_or(anyhow!("failed to create target machine"))
 }
+
+#[cfg(test)]
+mod tests {
+    use ast::cell::CellWidth;
+
+    use super::*;
+
+    /// `code`を変換したLLVM IR
+    fn lower(code: Vec<Ast>, cells: CellConfig, eof: EofPolicy) -> String {
+        let context = Context::create();
+        let mut compiler = Compiler::new(&context, host_machine().unwrap())
+            .with_cells(cells)
+            .with_eof(eof);
+        compiler.compile(AstCode::new(code));
+        compiler.module.to_string()
+    }
+
+    /// IRから`label`のブロックを取り出す
+    fn block<'a>(ir: &'a str, label: &str) -> &'a str {
+        let start = ir.find(&format!("\n{}:", label)).unwrap() + 1;
+        let end = ir[start..].find("\n\n").map_or(ir.len(), |end| start + end);
+        &ir[start..end]
+    }
+
+    #[test]
+    fn lowers_each_cell_width() {
+        let code = vec![Ast::InclementValue(1), Ast::MultiplyAdd(vec![(1, 3)])];
+        for width in [CellWidth::U8, CellWidth::U16, CellWidth::U32] {
+            let bits = width.bits();
+            let truncate = format!("to i{}", bits);
+
+            let ir = lower(
+                code.clone(),
+                CellConfig::new(width, Overflow::Wrapping),
+                EofPolicy::default(),
+            );
+            assert!(ir.contains(&format!("[30000 x i{}]", bits)), "{}", ir);
+            assert!(block(&ir, "multiply_add").contains("mul i128"), "{}", ir);
+            assert!(
+                ir.contains("trunc i128") && ir.contains(&truncate),
+                "{}",
+                ir
+            );
+            assert!(!ir.contains("icmp ule"), "{}", ir);
+
+            let ir = lower(
+                code.clone(),
+                CellConfig::new(width, Overflow::Checked),
+                EofPolicy::default(),
+            );
+            let multiply_add = block(&ir, "multiply_add");
+            assert!(multiply_add.contains("mul i128"), "{}", ir);
+            assert!(multiply_add.contains("icmp ule i128 %sum"), "{}", ir);
+            assert!(
+                multiply_add.contains(&format!(", {}\n", width.max())),
+                "{}",
+                ir
+            );
+            assert!(ir.contains("call void @exit(i32 1)"), "{}", ir);
+        }
+    }
+
+    #[test]
+    fn lowers_each_eof_policy() {
+        for (eof, expected) in [
+            (EofPolicy::Error, Some("call void @exit(i32 1)")),
+            (EofPolicy::Unchanged, None),
+            (EofPolicy::Zero, Some("store i8 0,")),
+            (EofPolicy::MinusOne, Some("store i8 -1,")),
+        ] {
+            let ir = lower(vec![Ast::Input], CellConfig::default(), eof);
+            let block = block(&ir, "eof");
+            match expected {
+                Some(expected) => assert!(block.contains(expected), "{:?}: {}", eof, block),
+                None => assert!(
+                    !block.contains("store") && !block.contains("@exit"),
+                    "{:?}: {}",
+                    eof,
+                    block
+                ),
+            }
+        }
+    }
+}
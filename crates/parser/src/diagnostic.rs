@@ -0,0 +1,87 @@
+use crate::parser::ParseError;
+
+/// エラー箇所のソースコードを示し，該当する文字の下にキャレットを付けて表示する
+///
+/// ```text
+/// error: unmatched ']'
+///  --> 2:4
+///   |
+/// 2 | +++]
+///   |    ^
+/// ```
+pub fn render(source: &str, error: &ParseError) -> String {
+    let span = error.span();
+    let line_number = span.line.to_string();
+    let gutter = " ".repeat(line_number.len());
+
+    let mut result = format!("error: {}\n", error.message());
+    result.push_str(&format!("{} --> {}:{}\n", gutter, span.line, span.column));
+
+    let Some(line) = source.lines().nth(span.line.saturating_sub(1)) else {
+        return result;
+    };
+
+    // タブ幅が環境によって異なるため，タブはそのまま残して位置を合わせる
+    let padding: String = line
+        .chars()
+        .take(span.column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+
+    result.push_str(&format!("{} |\n", gutter));
+    result.push_str(&format!("{} | {}\n", line_number, line));
+    result.push_str(&format!(
+        "{} | {}{}\n",
+        gutter,
+        padding,
+        "^".repeat(caret_len(line, span.column, span.len))
+    ));
+
+    result
+}
+
+/// キャレットの数．複数行にわたる範囲は行末までにする
+fn caret_len(line: &str, column: usize, len: usize) -> usize {
+    let rest = line
+        .chars()
+        .count()
+        .saturating_sub(column.saturating_sub(1));
+    len.min(rest).max(1)
+}
+
+/// 複数のエラーをまとめて表示する
+pub fn render_all(source: &str, errors: &[ParseError]) -> String {
+    errors
+        .iter()
+        .map(|error| render(source, error))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::Span;
+
+    #[test]
+    fn caret_under_bracket() {
+        let source = "+\n+++]\n";
+        let error = ParseError::UnmatchedRightBracket(Span::new(2, 4, 1));
+
+        assert_eq!(
+            render(source, &error),
+            "error: unmatched ']'\n  --> 2:4\n  |\n2 | +++]\n  |    ^\n"
+        );
+    }
+
+    #[test]
+    fn keeps_tabs_in_padding() {
+        let source = "\t[+";
+        let error = ParseError::UnmatchedLeftBracket(Span::new(1, 2, 1));
+
+        assert_eq!(
+            render(source, &error),
+            "error: unmatched '['\n  --> 1:2\n  |\n1 | \t[+\n  | \t^\n"
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use std::{error::Error, fmt::Display, sync::LazyLock};
+
+use crate::token::TokenType;
+
+/// Brainfuckの命令と同じ順に並べたトークンの種類
+const COMMANDS: [(char, TokenType); 8] = [
+    ('>', TokenType::RightAngle),
+    ('<', TokenType::LeftAngle),
+    ('+', TokenType::Plus),
+    ('-', TokenType::Minus),
+    ('.', TokenType::Dot),
+    (',', TokenType::Comma),
+    ('[', TokenType::LeftBracket),
+    (']', TokenType::RightBracket),
+];
+
+/// `Scanner::new`が使うBrainfuckの対応表
+pub(crate) static BRAINFUCK: LazyLock<Dialect> = LazyLock::new(Dialect::brainfuck);
+
+/// 対応表の読み込みエラー
+#[derive(Debug, PartialEq, Eq)]
+pub enum DialectError {
+    /// 行頭の文字がBrainfuckの命令ではない
+    UnknownCommand { line: usize, command: char },
+    /// 綴りが空
+    EmptyLexeme { line: usize },
+    /// 同じ綴りが複数の命令に割り当てられている
+    DuplicateLexeme(String),
+}
+
+impl Error for DialectError {}
+
+impl Display for DialectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DialectError::UnknownCommand { line, command } => {
+                write!(f, "line {}: unknown command '{}'", line, command)
+            }
+            DialectError::EmptyLexeme { line } => write!(f, "line {}: empty lexeme", line),
+            DialectError::DuplicateLexeme(lexeme) => {
+                write!(f, "lexeme '{}' is assigned to multiple commands", lexeme)
+            }
+        }
+    }
+}
+
+/// 命令の綴りとトークンの種類の対応表
+///
+/// 綴りの中の空白は，ソースコード上の1文字以上の任意の空白（改行を含む）にマッチする．
+/// どの綴りにもマッチしない文字はコメントとして読み飛ばされる
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dialect {
+    /// 綴りとトークンの種類．長い綴りから順に並べる
+    lexemes: Vec<(Vec<u8>, TokenType)>,
+    /// 綴りの先頭になりうるバイト
+    first_bytes: [bool; 256],
+}
+
+impl Dialect {
+    pub fn new(
+        lexemes: impl IntoIterator<Item = (String, TokenType)>,
+    ) -> Result<Self, DialectError> {
+        let mut result: Vec<(Vec<u8>, TokenType)> = Vec::new();
+        for (lexeme, token_type) in lexemes {
+            let lexeme = lexeme
+                .split_ascii_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            if result.iter().any(|(l, _)| *l == lexeme.as_bytes()) {
+                return Err(DialectError::DuplicateLexeme(lexeme));
+            }
+            result.push((lexeme.into_bytes(), token_type));
+        }
+
+        // 長い綴りを優先してマッチさせる
+        result.sort_by_key(|(lexeme, _)| std::cmp::Reverse(lexeme.len()));
+
+        let mut first_bytes = [false; 256];
+        for (lexeme, _) in result.iter() {
+            if let Some(&first) = lexeme.first() {
+                first_bytes[first as usize] = true;
+            }
+        }
+
+        Ok(Self {
+            lexemes: result,
+            first_bytes,
+        })
+    }
+
+    /// Brainfuck
+    pub fn brainfuck() -> Self {
+        Self::new(
+            COMMANDS
+                .iter()
+                .map(|(command, token_type)| (command.to_string(), *token_type)),
+        )
+        .unwrap()
+    }
+
+    /// Ook!
+    pub fn ook() -> Self {
+        Self::pairs("Ook")
+    }
+
+    /// Blub
+    pub fn blub() -> Self {
+        Self::pairs("Blub")
+    }
+
+    /// 名前から組み込みの対応表を得る
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "brainfuck" | "bf" => Some(Self::brainfuck()),
+            "ook" | "ook!" => Some(Self::ook()),
+            "blub" => Some(Self::blub()),
+            _ => None,
+        }
+    }
+
+    /// 対応表を読み込む
+    ///
+    /// 各行は命令の文字，空白，綴りの順に書く．空行と`#`で始まる行は無視する
+    ///
+    /// ```text
+    /// # Ook!
+    /// > Ook. Ook?
+    /// < Ook? Ook.
+    /// ```
+    pub fn from_table(table: &str) -> Result<Self, DialectError> {
+        let mut lexemes = Vec::new();
+        for (i, line) in table.lines().enumerate() {
+            let line_number = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut chars = line.chars();
+            let command = chars.next().unwrap();
+            let Some((_, token_type)) = COMMANDS.iter().find(|(c, _)| *c == command) else {
+                return Err(DialectError::UnknownCommand {
+                    line: line_number,
+                    command,
+                });
+            };
+
+            let lexeme = chars.as_str().trim();
+            if lexeme.is_empty() {
+                return Err(DialectError::EmptyLexeme { line: line_number });
+            }
+
+            lexemes.push((lexeme.to_string(), *token_type));
+        }
+
+        Self::new(lexemes)
+    }
+
+    /// `source`の先頭にマッチする綴りを探し，トークンの種類と綴りのバイト数を返す
+    pub(crate) fn match_at(&self, source: &[u8]) -> Option<(TokenType, usize)> {
+        let first = *source.first()?;
+        if !self.first_bytes[first as usize] {
+            return None;
+        }
+
+        self.lexemes.iter().find_map(|(lexeme, token_type)| {
+            match_lexeme(lexeme, source).map(|len| (*token_type, len))
+        })
+    }
+
+    /// Ook!のように，2つの単語の組で命令を表す対応表
+    fn pairs(word: &str) -> Self {
+        let words = [".", "?", "!"].map(|mark| format!("{}{}", word, mark));
+        let [dot, question, bang] = &words;
+        let pairs = [
+            (dot, question),
+            (question, dot),
+            (dot, dot),
+            (bang, bang),
+            (bang, dot),
+            (dot, bang),
+            (bang, question),
+            (question, bang),
+        ];
+
+        Self::new(
+            pairs
+                .iter()
+                .zip(COMMANDS.iter())
+                .map(|((a, b), (_, token_type))| (format!("{} {}", a, b), *token_type)),
+        )
+        .unwrap()
+    }
+}
+
+/// `source`の先頭が`lexeme`にマッチすれば，マッチしたバイト数を返す
+fn match_lexeme(lexeme: &[u8], source: &[u8]) -> Option<usize> {
+    let mut i = 0;
+    for &byte in lexeme {
+        if byte == b' ' {
+            let start = i;
+            while source.get(i).is_some_and(u8::is_ascii_whitespace) {
+                i += 1;
+            }
+            if i == start {
+                return None;
+            }
+        } else {
+            if source.get(i) != Some(&byte) {
+                return None;
+            }
+            i += 1;
+        }
+    }
+
+    Some(i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, scanner::Scanner};
+
+    #[test]
+    fn ook_parses_like_brainfuck() {
+        // +[-]>.
+        let ook = "Ook. Ook. Ook! Ook? Ook! Ook!\n Ook? Ook! Ook. Ook?  Ook!\n\tOok.";
+        let dialect = Dialect::ook();
+
+        assert_eq!(
+            Parser::new(Scanner::with_dialect(ook.as_bytes(), &dialect)).parse_tokens(),
+            Parser::new(Scanner::new(b"+[-]>.")).parse_tokens(),
+        );
+    }
+
+    #[test]
+    fn longest_lexeme_wins() {
+        let dialect = Dialect::from_table("+ inc\n> in\n# comment\n\n- incinc\n").unwrap();
+        let tokens: Vec<TokenType> = Scanner::with_dialect(b"incincinc in", &dialect)
+            .map(|token| token.token_type)
+            .collect();
+
+        assert_eq!(
+            tokens,
+            vec![TokenType::Minus, TokenType::Plus, TokenType::RightAngle]
+        );
+    }
+
+    #[test]
+    fn invalid_tables() {
+        assert_eq!(
+            Dialect::from_table("> a\nx b"),
+            Err(DialectError::UnknownCommand {
+                line: 2,
+                command: 'x'
+            })
+        );
+        assert_eq!(
+            Dialect::from_table("+"),
+            Err(DialectError::EmptyLexeme { line: 1 })
+        );
+        assert_eq!(
+            Dialect::from_table("+ a  b\n- a b"),
+            Err(DialectError::DuplicateLexeme("a b".to_string()))
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use crate::parser::{ParseError, Parser};
+use crate::scanner::Scanner;
+use crate::token::{Lexeme, TokenType};
+
+/// ソースコードの整形器
+///
+/// ループの本体は1段深くインデントし，`[`と`]`はそれぞれ1行に置く．
+/// 連続する命令は`width`に収まるようにまとめる．
+/// コメントは，命令と同じ行にあればその行の末尾に，そうでなければ独立した行に残す．
+/// 何度整形しても同じ結果になる
+#[derive(Debug, Clone)]
+pub struct Formatter {
+    /// 1行の最大文字数（インデントを含む）．コメントはこれを超えることがある
+    pub width: usize,
+    /// 1段のインデントの空白の数
+    pub indent: usize,
+}
+
+impl Default for Formatter {
+    fn default() -> Self {
+        Self {
+            width: 80,
+            indent: 4,
+        }
+    }
+}
+
+impl Formatter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// ソースコードを整形する．括弧の対応が取れていなければ構文解析エラーを返す
+    pub fn format(&self, source: &str) -> Result<String, Vec<ParseError>> {
+        Parser::new(Scanner::new(source.as_bytes())).parse_tokens()?;
+
+        let mut writer = Writer {
+            formatter: self,
+            result: String::new(),
+            code: String::new(),
+            depth: 0,
+        };
+        // 今読んでいる行に命令があるか
+        let mut code_on_line = false;
+        // 直前の字句から続く改行の数
+        let mut newlines = 0;
+
+        for lexeme in Scanner::new(source.as_bytes()).lexemes() {
+            match lexeme {
+                Lexeme::Newline => {
+                    code_on_line = false;
+                    newlines += 1;
+                    continue;
+                }
+                Lexeme::Comment(comment) => {
+                    let comment = String::from_utf8_lossy(comment);
+                    let comment = comment.trim();
+                    if comment.is_empty() {
+                        continue;
+                    }
+
+                    // 空行は1行にまとめて残す
+                    if newlines >= 2 {
+                        writer.push_blank_line();
+                    }
+
+                    if code_on_line && !writer.code.is_empty() {
+                        writer.push_trailing_comment(comment);
+                    } else {
+                        writer.flush();
+                        writer.push_line(comment);
+                    }
+                }
+                Lexeme::Token(token) => {
+                    if newlines >= 2 {
+                        writer.push_blank_line();
+                    }
+
+                    match token.token_type {
+                        TokenType::LeftBracket => {
+                            writer.flush();
+                            writer.push_line("[");
+                            writer.depth += 1;
+                        }
+                        TokenType::RightBracket => {
+                            writer.flush();
+                            writer.depth -= 1;
+                            writer.push_line("]");
+                        }
+                        TokenType::RightAngle => writer.push_command('>'),
+                        TokenType::LeftAngle => writer.push_command('<'),
+                        TokenType::Plus => writer.push_command('+'),
+                        TokenType::Minus => writer.push_command('-'),
+                        TokenType::Dot => writer.push_command('.'),
+                        TokenType::Comma => writer.push_command(','),
+                    }
+                    code_on_line = true;
+                }
+            }
+
+            newlines = 0;
+        }
+
+        writer.flush();
+
+        Ok(writer.result)
+    }
+}
+
+/// 整形結果を行単位で書き出す
+struct Writer<'a> {
+    formatter: &'a Formatter,
+    /// 書き出した行
+    result: String,
+    /// まだ書き出していない命令
+    code: String,
+    /// ループのネストの深さ
+    depth: usize,
+}
+
+impl Writer<'_> {
+    fn indent(&self) -> String {
+        " ".repeat(self.depth * self.formatter.indent)
+    }
+
+    fn push_line(&mut self, line: &str) {
+        self.result.push_str(&self.indent());
+        self.result.push_str(line);
+        self.result.push('\n');
+    }
+
+    fn push_blank_line(&mut self) {
+        self.flush();
+        if !self.result.is_empty() && !self.result.ends_with("\n\n") {
+            self.result.push('\n');
+        }
+    }
+
+    fn push_command(&mut self, command: char) {
+        let indent = self.depth * self.formatter.indent;
+        if !self.code.is_empty() && indent + self.code.len() >= self.formatter.width {
+            self.flush();
+        }
+        self.code.push(command);
+    }
+
+    fn push_trailing_comment(&mut self, comment: &str) {
+        let line = format!("{} {}", std::mem::take(&mut self.code), comment);
+        self.push_line(&line);
+    }
+
+    fn flush(&mut self) {
+        if self.code.is_empty() {
+            return;
+        }
+        let code = std::mem::take(&mut self.code);
+        self.push_line(&code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> ast::inst::AstCode {
+        Parser::new(Scanner::new(source.as_bytes()))
+            .parse_tokens()
+            .unwrap()
+    }
+
+    #[test]
+    fn format_loops_and_comments() {
+        let source = "set ++++\n\n\n++ [>+ # inner\n<-]  done\n";
+        let formatter = Formatter {
+            width: 8,
+            indent: 2,
+        };
+
+        assert_eq!(
+            formatter.format(source).unwrap(),
+            "set\n++++\n\n++\n[\n  >+ # inner\n  <-\n]\ndone\n"
+        );
+    }
+
+    #[test]
+    fn wraps_long_runs() {
+        let formatter = Formatter {
+            width: 4,
+            indent: 2,
+        };
+
+        assert_eq!(
+            formatter.format("+++++[->>>+<<<]").unwrap(),
+            "++++\n+\n[\n  ->\n  >>\n  +<\n  <<\n]\n"
+        );
+    }
+
+    #[test]
+    fn idempotent_and_preserves_program() {
+        let programs = [
+            include_str!("../../../programs/mandelbrot.bf"),
+            "+++++++[>++++++++++<-]>++.        # H\n<++[>++++++++++<-]>+++++++++.     # e\n",
+            "  [ # start\n\n\n   -] # end\n",
+        ];
+
+        for program in programs {
+            let formatter = Formatter::new();
+            let formatted = formatter.format(program).unwrap();
+
+            assert_eq!(formatter.format(&formatted).unwrap(), formatted);
+            assert_eq!(parse(&formatted), parse(program));
+        }
+    }
+
+    #[test]
+    fn rejects_unbalanced_brackets() {
+        assert!(Formatter::new().format("[[]").is_err());
+    }
+}
@@ -1,9 +1,6 @@
-use std::io::{Read, Write};
-
-use bytecode_backend::interpreter::Interpreter;
-use parser::Parser;
-use scanner::Scanner;
-
+pub mod diagnostic;
+pub mod dialect;
+pub mod formatter;
 pub mod parser;
 pub mod scanner;
 pub mod token;
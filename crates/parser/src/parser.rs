@@ -1,118 +1,233 @@
 use std::{error::Error, fmt::Display};
 
-use crate::token::{Token, TokenType};
+use crate::token::{Span, Token, TokenType};
 
 use ast::inst::{Ast, AstCode};
+use ast::source_map::{SourceMap, SourceNode, SourceRange};
 
 /// 構文解析エラー
 #[derive(Debug, PartialEq, Eq)]
 pub enum ParseError {
-    /// LeftBracketとRightBracketの対応関係の不備
-    IncompleteLoop,
+    /// 対応するRightBracketがないLeftBracket
+    UnmatchedLeftBracket(Span),
+    /// 対応するLeftBracketがないRightBracket
+    UnmatchedRightBracket(Span),
+}
+
+impl ParseError {
+    /// エラーの原因となったトークンの範囲
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::UnmatchedLeftBracket(span) | ParseError::UnmatchedRightBracket(span) => {
+                *span
+            }
+        }
+    }
+
+    /// 位置情報を含まないエラーメッセージ
+    pub fn message(&self) -> &'static str {
+        match self {
+            ParseError::UnmatchedLeftBracket(_) => "unmatched '['",
+            ParseError::UnmatchedRightBracket(_) => "unmatched ']'",
+        }
+    }
 }
 
 impl Error for ParseError {}
 
 impl Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ParseError::IncompleteLoop => write!(f, "incomplete loop"),
-        }
+        let span = self.span();
+        write!(f, "{}:{}: {}", span.line, span.column, self.message())
     }
 }
 
 /// 構文解析器
-pub struct Parser {
+///
+/// トークンを1つずつ読むので，`Vec<Token>`の他に`Scanner`を直接渡せる
+pub struct Parser<I: Iterator<Item = Token>> {
     /// トークン化済みのソースコード
-    tokens: Vec<Token>,
-    /// 現在の位置
-    current: usize,
+    tokens: I,
+    /// 発見した構文解析エラー
+    errors: Vec<ParseError>,
 }
 
-impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
+impl<I: Iterator<Item = Token>> Parser<I> {
+    pub fn new(tokens: impl IntoIterator<Item = Token, IntoIter = I>) -> Self {
         Self {
-            tokens,
-            current: 0,
-            // jump_stack: Vec::new(),
-            // result: Vec::new(),
+            tokens: tokens.into_iter(),
+            errors: Vec::new(),
         }
     }
 
-    pub fn parse_tokens(mut self) -> Result<AstCode, Vec<ParseError>> {
-        let mut errors = Vec::new();
-        let mut result: Vec<Ast> = Vec::new();
-        while !self.is_at_end() {
-            match self.parse_instruction() {
-                Ok(op) => result.push(op),
-                Err(e) => errors.push(e),
-            }
+    /// 全てのトークンを構文解析する
+    ///
+    /// エラーがあっても解析を続け，見つかった全てのエラーをソースコード上の順に返す．
+    /// ループのネストは再帰ではなくスタックで管理するので，深くネストしたプログラムも扱える
+    pub fn parse_tokens(self) -> Result<AstCode, Vec<ParseError>> {
+        self.parse_tokens_mapped().map(|(code, _)| code)
+    }
+
+    /// `parse_tokens`と同じく構文解析し，各ノードの元になったトークンの添字の範囲も返す
+    pub fn parse_tokens_mapped(mut self) -> Result<(AstCode, SourceMap), Vec<ParseError>> {
+        // blocks[0]はトップレベル，blocks[i + 1]はloop_starts[i]から始まるループの本体
+        let mut blocks: Vec<Vec<Ast>> = vec![Vec::new()];
+        let mut maps: Vec<Vec<SourceNode>> = vec![Vec::new()];
+        let mut loop_starts: Vec<(Span, usize)> = Vec::new();
+
+        for (index, token) in self.tokens.by_ref().enumerate() {
+            let span = token.span();
+            let mut node = SourceNode::new(SourceRange::token(index));
+            let op = match token.token_type() {
+                TokenType::Plus => Ast::InclementValue(1),
+                TokenType::Minus => Ast::DecrementValue(1),
+                TokenType::RightAngle => Ast::InclementPointer(1),
+                TokenType::LeftAngle => Ast::DecrementPointer(1),
+                TokenType::Comma => Ast::Input,
+                TokenType::Dot => Ast::Output,
+                TokenType::LeftBracket => {
+                    loop_starts.push((span, index));
+                    blocks.push(Vec::new());
+                    maps.push(Vec::new());
+                    continue;
+                }
+                TokenType::RightBracket => {
+                    let Some((_, start)) = loop_starts.pop() else {
+                        self.errors.push(ParseError::UnmatchedRightBracket(span));
+                        continue;
+                    };
+                    node.range = SourceRange::new(start, index + 1);
+                    node.body = SourceMap::new(maps.pop().unwrap());
+                    let body = blocks.pop().unwrap();
+                    Ast::Loop(AstCode::new(body))
+                }
+            };
+
+            blocks.last_mut().unwrap().push(op);
+            maps.last_mut().unwrap().push(node);
         }
 
-        if errors.is_empty() {
-            Ok(AstCode::new(result))
+        for (start, _) in loop_starts {
+            self.errors.push(ParseError::UnmatchedLeftBracket(start));
+        }
+
+        if self.errors.is_empty() {
+            Ok((
+                AstCode::new(blocks.pop().unwrap()),
+                SourceMap::new(maps.pop().unwrap()),
+            ))
         } else {
-            Err(errors)
+            self.errors
+                .sort_by_key(|error| (error.span().line, error.span().column));
+            Err(self.errors)
         }
     }
+}
 
-    fn parse_instruction(&mut self) -> Result<Ast, ParseError> {
-        let token = self.advance();
-        let token_type = token.token_type();
-        let op = match token_type {
-            TokenType::Plus => Ast::InclementValue(1),
-            TokenType::Minus => Ast::DecrementValue(1),
-            TokenType::RightAngle => Ast::InclementPointer(1),
-            TokenType::LeftAngle => Ast::DecrementPointer(1),
-            TokenType::Comma => Ast::Input,
-            TokenType::Dot => Ast::Output,
-            TokenType::LeftBracket => {
-                // self.jump_stack.push(self.current);
-                // Op::LoopStart { if_zero: 0 }
-                Ast::Loop(self.parse_loop())
-            }
-            TokenType::RightBracket => {
-                // let loop_start = self.jump_stack.pop().ok_or(ParseError::IncompleteLoop)?;
-                // let loop_end = self.current;
-                // if let Op::LoopStart { if_zero } = &mut self.result[loop_start - 1] {
-                //     *if_zero = loop_end;
-                // } else {
-                //     return Err(ParseError::IncompleteLoop);
-                // };
-
-                // Op::LoopEnd {
-                //     if_non_zero: loop_start,
-                // }
-                return Err(ParseError::IncompleteLoop);
-            }
-        };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::Scanner;
 
-        Ok(op)
+    fn parse(source: &str) -> Result<AstCode, Vec<ParseError>> {
+        Parser::new(Scanner::new(source.as_bytes())).parse_tokens()
     }
 
-    fn parse_loop(&mut self) -> AstCode {
-        let mut result: Vec<Ast> = Vec::new();
-
-        while *self.peek().token_type() != TokenType::RightBracket {
-            result.push(self.parse_instruction().unwrap());
-        }
+    #[test]
+    fn unmatched_right_bracket_has_position() {
+        assert_eq!(
+            parse("+\n+]"),
+            Err(vec![ParseError::UnmatchedRightBracket(Span::new(2, 2, 1))])
+        );
+    }
 
-        self.advance();
+    #[test]
+    fn unmatched_left_bracket_has_position() {
+        assert_eq!(
+            parse("++[-"),
+            Err(vec![ParseError::UnmatchedLeftBracket(Span::new(1, 3, 1))])
+        );
+    }
 
-        AstCode::new(result)
+    #[test]
+    fn reports_every_unmatched_bracket() {
+        assert_eq!(
+            parse("[[+\n]]]]"),
+            Err(vec![
+                ParseError::UnmatchedRightBracket(Span::new(2, 3, 1)),
+                ParseError::UnmatchedRightBracket(Span::new(2, 4, 1)),
+            ])
+        );
+        assert_eq!(
+            parse("][[+"),
+            Err(vec![
+                ParseError::UnmatchedRightBracket(Span::new(1, 1, 1)),
+                ParseError::UnmatchedLeftBracket(Span::new(1, 2, 1)),
+                ParseError::UnmatchedLeftBracket(Span::new(1, 3, 1)),
+            ])
+        );
     }
 
-    fn advance(&mut self) -> &Token {
-        let token = &self.tokens[self.current];
-        self.current += 1;
-        token
+    #[test]
+    fn never_panics_on_brackets() {
+        // 長さ8までの全ての括弧列
+        for len in 0..=8 {
+            for bits in 0..(1u32 << len) {
+                let source: String = (0..len)
+                    .map(|i| if bits & (1 << i) == 0 { '[' } else { ']' })
+                    .collect();
+                let _ = parse(&source);
+            }
+        }
     }
 
-    fn peek(&self) -> &Token {
-        &self.tokens[self.current]
+    #[test]
+    fn maps_optimized_ops_to_tokens() {
+        use ast::inst::{Op, OpCode};
+        use ast::opt::Optimizer;
+
+        let tokens = Scanner::new("+[->+<]\n>.".as_bytes()).scan_tokens();
+        let (code, map) = Parser::new(tokens).parse_tokens_mapped().unwrap();
+        let (code, map, _) = Optimizer::new().optimize_mapped(code, map);
+        let (code, ranges) = OpCode::from_mapped(code, map);
+
+        assert_eq!(
+            code.vec(),
+            &vec![
+                Op::InclementValueAt {
+                    offset: 0,
+                    count: 1
+                },
+                Op::SumRight(1),
+                Op::OutputAt { offset: 1 },
+                Op::InclementPointer(1),
+            ]
+        );
+        assert_eq!(
+            ranges,
+            vec![
+                SourceRange::new(0, 1),
+                SourceRange::new(1, 7),
+                SourceRange::new(8, 9),
+                SourceRange::new(7, 8),
+            ]
+        );
     }
 
-    fn is_at_end(&self) -> bool {
-        self.current >= self.tokens.len()
+    #[test]
+    fn deeply_nested_loops() {
+        const DEPTH: usize = 1_000_000;
+        let source = "[".repeat(DEPTH) + "+" + &"]".repeat(DEPTH);
+
+        let program = parse(&source).unwrap();
+
+        let mut depth = 0;
+        let mut code = &program;
+        while let [Ast::Loop(body)] = &code.vec()[..] {
+            depth += 1;
+            code = body;
+        }
+        assert_eq!(depth, DEPTH);
+        assert_eq!(code.vec(), &vec![Ast::InclementValue(1)]);
     }
 }
@@ -1,10 +1,16 @@
-use crate::token::{Token, TokenType};
+use crate::dialect::{Dialect, BRAINFUCK};
+use crate::token::{Lexeme, Token};
 
 /// 字句解析器
-#[derive(Debug, Clone, Default)]
-pub struct Scanner {
+///
+/// ソースコードをバイト列のまま借用して読むので，文字列全体をコピーしない．
+/// `Iterator`として1トークンずつ取り出せる
+#[derive(Debug, Clone)]
+pub struct Scanner<'a> {
     /// ソースコード
-    source: Vec<char>,
+    source: &'a [u8],
+    /// 命令の綴り
+    dialect: &'a Dialect,
     /// 現在の位置
     current: usize,
     /// 現在の列
@@ -13,58 +19,100 @@ pub struct Scanner {
     line: usize,
 }
 
-impl Scanner {
-    pub fn new(source: Vec<char>) -> Self {
+impl<'a> Scanner<'a> {
+    pub fn new(source: &'a [u8]) -> Self {
+        Self::with_dialect(source, &BRAINFUCK)
+    }
+
+    /// Brainfuck以外の綴りで書かれたソースコードを読む
+    pub fn with_dialect(source: &'a [u8], dialect: &'a Dialect) -> Self {
         Self {
             source,
+            dialect,
             current: 0,
             column: 0,
-            line: 0,
+            line: 1,
         }
     }
 
     /// 全てのトークンをスキャンする
     pub fn scan_tokens(&mut self) -> Vec<Token> {
-        let mut result: Vec<Token> = Vec::new();
+        self.collect()
+    }
 
-        while !self.is_at_end() {
-            let Some(token) = self.scan_token() else {
-                continue;
-            };
+    /// コメントと改行も含めて，全ての字句を順に取り出す
+    ///
+    /// 字句を全て連結すると元のソースコードに戻る
+    pub fn lexemes(mut self) -> impl Iterator<Item = Lexeme<'a>> {
+        std::iter::from_fn(move || self.scan_lexeme())
+    }
 
-            result.push(token);
+    /// 現在の字句をスキャンする
+    fn scan_lexeme(&mut self) -> Option<Lexeme<'a>> {
+        if self.is_at_end() {
+            return None;
         }
 
-        result
+        if self.source[self.current] == b'\n' {
+            self.advance();
+            return Some(Lexeme::Newline);
+        }
+
+        if let Some(token) = self.match_token() {
+            return Some(Lexeme::Token(token));
+        }
+
+        let start = self.current;
+        while !self.is_at_end()
+            && self.source[self.current] != b'\n'
+            && self
+                .dialect
+                .match_at(&self.source[self.current..])
+                .is_none()
+        {
+            self.advance();
+        }
+
+        Some(Lexeme::Comment(&self.source[start..self.current]))
     }
 
     /// 現在のトークンをスキャンする
     fn scan_token(&mut self) -> Option<Token> {
-        let c = self.advance();
-        let token_type = match c {
-            '>' => TokenType::RightAngle,
-            '<' => TokenType::LeftAngle,
-            '+' => TokenType::Plus,
-            '-' => TokenType::Minus,
-            '.' => TokenType::Dot,
-            ',' => TokenType::Comma,
-            '[' => TokenType::LeftBracket,
-            ']' => TokenType::RightBracket,
-            '\n' => {
-                self.line += 1;
-                self.column = 0;
-                return None;
-            }
-            _ => return None,
-        };
+        let token = self.match_token();
+        if token.is_none() {
+            // どの綴りにもマッチしない文字はコメント
+            self.advance();
+        }
+        token
+    }
 
-        Some(Token::new(token_type, self.column, self.line))
+    /// 現在の位置から始まる綴りがあれば，トークンとして読み進める
+    fn match_token(&mut self) -> Option<Token> {
+        let (token_type, len) = self.dialect.match_at(&self.source[self.current..])?;
+
+        let (line, column) = (self.line, self.column + 1);
+        let start = self.current;
+        for _ in 0..len {
+            self.advance();
+        }
+        let chars = self.source[start..self.current]
+            .iter()
+            .filter(|&&byte| !is_continuation_byte(byte))
+            .count();
+
+        Some(Token::new(token_type, column, line, chars))
     }
 
-    fn advance(&mut self) -> char {
+    fn advance(&mut self) -> u8 {
         let result = self.source[self.current];
         self.current += 1;
-        self.column += 1;
+        if result == b'\n' {
+            self.line += 1;
+            self.column = 0;
+        } else if !is_continuation_byte(result) {
+            // UTF-8の継続バイトは前の文字と同じ列として数える
+            self.column += 1;
+        }
         result
     }
 
@@ -72,3 +120,59 @@ impl Scanner {
         self.current >= self.source.len()
     }
 }
+
+fn is_continuation_byte(byte: u8) -> bool {
+    byte & 0b1100_0000 == 0b1000_0000
+}
+
+impl Iterator for Scanner<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.is_at_end() {
+            if let Some(token) = self.scan_token() {
+                return Some(token);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::TokenType;
+
+    #[test]
+    fn positions_count_characters() {
+        let tokens: Vec<(TokenType, usize, usize)> = Scanner::new("あ+\n い-".as_bytes())
+            .map(|token| (token.token_type, token.line, token.column))
+            .collect();
+
+        assert_eq!(
+            tokens,
+            vec![(TokenType::Plus, 1, 2), (TokenType::Minus, 2, 3)]
+        );
+    }
+
+    #[test]
+    fn lexemes_are_lossless() {
+        let source = "+ a\n\n[>]b ";
+        let lexemes: Vec<Lexeme> = Scanner::new(source.as_bytes()).lexemes().collect();
+
+        assert_eq!(
+            lexemes,
+            vec![
+                Lexeme::Token(Token::new(TokenType::Plus, 1, 1, 1)),
+                Lexeme::Comment(b" a"),
+                Lexeme::Newline,
+                Lexeme::Newline,
+                Lexeme::Token(Token::new(TokenType::LeftBracket, 1, 3, 1)),
+                Lexeme::Token(Token::new(TokenType::RightAngle, 2, 3, 1)),
+                Lexeme::Token(Token::new(TokenType::RightBracket, 3, 3, 1)),
+                Lexeme::Comment(b"b "),
+            ]
+        );
+    }
+}
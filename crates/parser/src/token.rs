@@ -1,28 +1,65 @@
 /// トークン
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Token {
     pub token_type: TokenType,
     /// ソースコード上の列番号
     pub column: usize,
     /// ソースコード上の行番号
     pub line: usize,
+    /// 綴りの文字数
+    pub len: usize,
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, column: usize, line: usize) -> Self {
+    pub fn new(token_type: TokenType, column: usize, line: usize, len: usize) -> Self {
         Self {
             token_type,
             column,
             line,
+            len,
         }
     }
 
     pub fn token_type(&self) -> &TokenType {
         &self.token_type
     }
+
+    /// トークンのソースコード上の範囲
+    pub fn span(&self) -> Span {
+        Span::new(self.line, self.column, self.len)
+    }
+}
+
+/// コメントや改行も含めた字句
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lexeme<'a> {
+    /// 命令
+    Token(Token),
+    /// 命令以外の文字の並び．改行は含まない
+    Comment(&'a [u8]),
+    /// 改行
+    Newline,
+}
+
+/// ソースコード上の範囲
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    /// 開始位置の行番号（1始まり）
+    pub line: usize,
+    /// 開始位置の列番号（1始まり）
+    pub column: usize,
+    /// 文字数
+    pub len: usize,
+}
+
+impl Span {
+    pub fn new(line: usize, column: usize, len: usize) -> Self {
+        Self { line, column, len }
+    }
 }
 
 /// トークンの種類
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub enum TokenType {
     /// >
     RightAngle,
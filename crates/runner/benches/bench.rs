@@ -1,10 +1,12 @@
 #![feature(test)]
 extern crate test;
 
-use std::io::{stdin, stdout};
+use std::io::{empty, sink};
 
+use ast::inst::{AstCode, OpCode};
 use ast::opt::Optimizer;
 use bytecode_backend::interpreter::Interpreter;
+use bytecode_backend::tape::TapeConfig;
 // use inkwell::{
 //     context::Context,
 //     targets::{self, TargetMachine},
@@ -14,14 +16,15 @@ use parser::parser::Parser;
 use parser::scanner::Scanner;
 use test::Bencher;
 
-#[bench]
-fn bench_mandelbrot(b: &mut Bencher) {
-    const PROGRAM: &str = include_str!("../../../programs/mandelbrot.bf");
+/// 置き換えられないループの中で，ポインタの移動とセルの増減を繰り返す
+///
+/// 一番内側の`[>+<-]`は`SumRight`になり，その外側のループを32 * 255 * 255周する
+const NESTED_LOOPS: &str = "++++++++++++++++++++++++++++++++[>-[>-[>-[>+<-]<-]<-]<-]";
 
-    let mut scanner = Scanner::new(PROGRAM.chars().collect());
-    let tokens = scanner.scan_tokens();
+fn parse(program: &str) -> AstCode {
+    let scanner = Scanner::new(program.as_bytes());
 
-    let parser = Parser::new(tokens);
+    let parser = Parser::new(scanner);
     let parse_result = parser.parse_tokens();
     let Ok(program) = parse_result else {
         for error in parse_result.unwrap_err() {
@@ -29,15 +32,57 @@ fn bench_mandelbrot(b: &mut Bencher) {
         }
         panic!("failed to parse tokens");
     };
+    program
+}
 
-    let optimizer = Optimizer::new();
-    let insts = optimizer.optimize(program);
+fn bench_tape(b: &mut Bencher, tape: TapeConfig) {
+    let op_code: OpCode = Optimizer::new()
+        .with_tape(tape)
+        .optimize(parse(NESTED_LOOPS))
+        .into();
+
+    b.iter(|| {
+        Interpreter::new(op_code.clone(), empty(), sink())
+            .with_tape(tape)
+            .run()
+            .unwrap()
+    });
+}
+
+#[bench]
+fn bench_fixed_tape(b: &mut Bencher) {
+    bench_tape(b, TapeConfig::default());
+}
+
+#[bench]
+fn bench_grow_right_tape(b: &mut Bencher) {
+    bench_tape(b, TapeConfig::GrowRight(1));
+}
+
+#[bench]
+fn bench_unbounded_tape(b: &mut Bencher) {
+    bench_tape(b, TapeConfig::Unbounded);
+}
 
-    let op_code = insts.into();
+#[bench]
+fn bench_circular_tape(b: &mut Bencher) {
+    bench_tape(b, TapeConfig::Circular(256));
+}
+
+/// 1回に数秒かかるので，`cargo bench -- --ignored`で実行する
+#[bench]
+#[ignore]
+fn bench_mandelbrot(b: &mut Bencher) {
+    const PROGRAM: &str = include_str!("../../../programs/mandelbrot.bf");
 
-    let mut interpreter = Interpreter::new(op_code, stdin(), stdout());
+    let optimizer = Optimizer::new();
+    let op_code: OpCode = optimizer.optimize(parse(PROGRAM)).into();
 
-    b.iter(|| interpreter.run());
+    b.iter(|| {
+        Interpreter::new(op_code.clone(), empty(), sink())
+            .run()
+            .unwrap()
+    });
 
     // let context = Context::create();
     // let machine = host_machine().unwrap();
@@ -46,3 +91,13 @@ fn bench_mandelbrot(b: &mut Bencher) {
 
     // b.iter(|| compiler.run_jit().unwrap())
 }
+
+#[bench]
+fn bench_parse_mandelbrot(b: &mut Bencher) {
+    const PROGRAM: &str = include_str!("../../../programs/mandelbrot.bf");
+
+    b.iter(|| {
+        let scanner = Scanner::new(PROGRAM.as_bytes());
+        Parser::new(scanner).parse_tokens().unwrap()
+    });
+}
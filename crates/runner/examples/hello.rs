@@ -1,4 +1,4 @@
-use runner::run;
+use runner::{run, OptLevel};
 
 use std::io::{stdin, stdout};
 
@@ -19,5 +19,8 @@ fn main() {
         >>+.                              # !
     ";
 
-    run(program, stdin(), stdout());
+    if let Err(e) = run(program, OptLevel::default(), stdin(), stdout()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 }
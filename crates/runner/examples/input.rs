@@ -1,4 +1,4 @@
-use runner::run;
+use runner::{run, OptLevel};
 
 use std::io::{stdin, stdout};
 
@@ -7,5 +7,8 @@ fn main() {
         ,.,.,.
     ";
 
-    run(program, stdin(), stdout());
+    if let Err(e) = run(program, OptLevel::default(), stdin(), stdout()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 }
@@ -1,9 +1,12 @@
 use std::io::{stdin, stdout, Write};
 
-use runner::run;
+use runner::{run, OptLevel};
 
 fn main() {
     let program = include_str!("../../../programs/mandelbrot.bf");
-    run(program, stdin(), stdout());
+    if let Err(e) = run(program, OptLevel::default(), stdin(), stdout()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
     stdout().flush().unwrap();
 }
@@ -1,25 +1,35 @@
 use std::io::{Read, Write};
 
+pub use ast::opt::OptLevel;
 use ast::opt::Optimizer;
+use bytecode_backend::error::RuntimeError;
 use bytecode_backend::interpreter::Interpreter;
-use parser::{parser::Parser, scanner::Scanner};
+use parser::{diagnostic, parser::Parser, scanner::Scanner};
 
-pub fn run(string: &str, read: impl Read, write: impl Write) {
-    let mut scanner = Scanner::new(string.chars().collect());
-    let tokens = scanner.scan_tokens();
+/// ソースコードを最適化レベル`level`で最適化して実行する
+///
+/// 構文エラーは標準エラー出力に書き出して実行しない．実行時エラーは呼び出し元に返す
+pub fn run(
+    string: &str,
+    level: OptLevel,
+    read: impl Read,
+    write: impl Write,
+) -> Result<(), RuntimeError> {
+    let scanner = Scanner::new(string.as_bytes());
 
-    let parser = Parser::new(tokens);
+    let parser = Parser::new(scanner);
     let parse_result = parser.parse_tokens();
-    let Ok(program) = parse_result else {
-        for error in parse_result.unwrap_err() {
-            eprintln!("{}", error);
+    let program = match parse_result {
+        Ok(program) => program,
+        Err(errors) => {
+            eprint!("{}", diagnostic::render_all(string, &errors));
+            return Ok(());
         }
-        return;
     };
 
     // println!("{:?}\n\n", &program.vec()[..100]);
 
-    let optimizer = Optimizer::new();
+    let optimizer = Optimizer::with_level(level);
     let program = optimizer.optimize(program);
 
     // println!("{:?}", &program.vec()[..]);
@@ -29,5 +39,102 @@ pub fn run(string: &str, read: impl Read, write: impl Write) {
     // println!("{:?}", code);
 
     let mut interpreter = Interpreter::new(code, read, write);
-    interpreter.run();
+    interpreter.run()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::emit::emit;
+
+    fn output(program: &str) -> Vec<u8> {
+        output_with_level(program, OptLevel::default())
+    }
+
+    fn output_with_level(program: &str, level: OptLevel) -> Vec<u8> {
+        output_with_input(program, level, b"")
+    }
+
+    fn output_with_input(program: &str, level: OptLevel, input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        run(program, level, input, &mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn returns_runtime_error() {
+        let mut output = Vec::new();
+        let error = run(".<", OptLevel::O0, &b""[..], &mut output).unwrap_err();
+        assert!(matches!(
+            error.kind,
+            bytecode_backend::error::RuntimeErrorKind::PointerUnderflow
+        ));
+        assert_eq!(error.ip, 1);
+        assert_eq!(output, [0]);
+    }
+
+    #[test]
+    fn optimized_round_trip() {
+        let programs = [
+            "+++++++[>++++++++++<-]>++.<++[>++++++++++<-]>+++++++++.+++++++..+++.",
+            "++++++++[->+++++++++<]>[->+<]>.<+.[-]+++[-<+>]<.[>]<.",
+        ];
+
+        for program in programs {
+            let parsed = Parser::new(Scanner::new(program.as_bytes()))
+                .parse_tokens()
+                .unwrap();
+            let emitted = emit(&Optimizer::new().optimize(parsed));
+
+            assert_eq!(output(&emitted), output(program));
+        }
+
+        assert_eq!(output(programs[0]), b"Hello");
+        assert_eq!(output(programs[1]), b"H\x01\x03\x03");
+    }
+
+    #[test]
+    fn every_level_agrees() {
+        let program = "++++++++[->+++++++++<]>[->+<]>.<+.[-]+++[-<+>]<.[>]<.";
+        for level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::O3] {
+            assert_eq!(output_with_level(program, level), b"H\x01\x03\x03");
+        }
+    }
+
+    #[test]
+    fn input_free_program_becomes_constant_print() {
+        let program = "+++++++[>++++++++++<-]>++.<++[>++++++++++<-]>+++++++++.+++++++..+++.";
+        let parsed = Parser::new(Scanner::new(program.as_bytes()))
+            .parse_tokens()
+            .unwrap();
+        let optimized = Optimizer::with_level(OptLevel::O3).optimize(parsed);
+
+        // 出力の後は最後のテープとポインタを作るだけで，ループも入力も残らない
+        assert!(optimized.vec().iter().all(|ast| matches!(
+            ast,
+            ast::inst::Ast::Load(_)
+                | ast::inst::Ast::Output
+                | ast::inst::Ast::InclementPointer(_)
+                | ast::inst::Ast::DecrementPointer(_)
+        )));
+        assert_eq!(output_with_level(program, OptLevel::O3), b"Hello");
+    }
+
+    #[test]
+    fn dead_loops_keep_behavior() {
+        let programs = [
+            "[.>]+++[>++<-][>.<]>.",
+            ",[.[-]][.],>[-]+++++[-]++.<[>]",
+            ">,[>+>++<<-]>[-]+.>>[<]<.[-]>",
+            ",[-]++++[>+++<-]>[.-]",
+        ];
+
+        for program in programs {
+            let expected = output_with_input(program, OptLevel::O0, b"AB");
+            for level in [OptLevel::O1, OptLevel::O2, OptLevel::O3] {
+                assert_eq!(output_with_input(program, level, b"AB"), expected);
+            }
+        }
+    }
 }